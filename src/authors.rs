@@ -0,0 +1,94 @@
+use crate::config::SiteConfig;
+use crate::error::{Error, Position, Result};
+use crate::post::Post;
+use serde::{Deserialize, Serialize};
+use slug::slugify;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Author id as used in front matter -> author.
+pub type Authors = BTreeMap<String, Author>;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Link {
+    pub name: String,
+    pub url: String,
+}
+
+/// An entry of the authors file, e.g.
+///
+/// ```toml
+/// [jane]
+/// name = "Jane Doe"
+/// bio = "Writes about compilers"
+/// avatar = "/images/jane.webp"
+/// links = [{ name = "GitHub", url = "https://github.com/jane" }]
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Author {
+    pub name: String,
+    #[serde(default)]
+    pub bio: Option<String>,
+    #[serde(default)]
+    pub avatar: Option<String>,
+    /// Shown in the feed
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub links: Vec<Link>,
+    /// Derived from the id
+    #[serde(default, skip_deserializing)]
+    pub slug: String,
+    /// Site relative url of the author page
+    #[serde(default, skip_deserializing)]
+    pub permalink: String,
+}
+
+pub fn author_permalink(slug: &str) -> String {
+    format!("/authors/{slug}/")
+}
+
+pub fn parse(contents: &str) -> Result<Authors, toml::de::Error> {
+    let mut authors: Authors = toml::from_str(contents)?;
+    for (id, author) in authors.iter_mut() {
+        author.slug = slugify(id);
+        author.permalink = author_permalink(&author.slug);
+    }
+    Ok(authors)
+}
+
+/// Load the authors file, a missing file means there are no authors.
+pub fn load(config: &SiteConfig) -> Result<Authors> {
+    let path = Path::new(&config.authors_file);
+    if !path.exists() {
+        return Ok(Authors::new());
+    }
+    let contents = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+    parse(&contents).map_err(|e| {
+        let position = e.span().map(|span| Position::from_offset(&contents, span.start));
+        Error::source(path, &contents, position, e.message())
+    })
+}
+
+/// Authors of a post, in the order of its front matter. Unknown ids are skipped, see `check`.
+pub fn post_authors<'a>(authors: &'a Authors, post: &Post) -> Vec<&'a Author> {
+    post.metadata.authors.iter().filter_map(|id| authors.get(id)).collect()
+}
+
+/// Error for every post referring to an author missing from the authors file.
+pub fn check(config: &SiteConfig, authors: &Authors, posts: &[Post]) -> Result<()> {
+    let errors = posts
+        .iter()
+        .flat_map(|post| {
+            post.metadata.authors.iter().filter(|id| !authors.contains_key(*id)).map(|id| Error::Source {
+                path: post.path.clone(),
+                position: None,
+                snippet: None,
+                message: format!("unknown author {id:?}, add it to {}", config.authors_file),
+            })
+        })
+        .collect();
+    Error::collect(errors)
+}
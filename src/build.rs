@@ -1,113 +1,1147 @@
-use crate::html::{minify_css, minify_html, TEMPLATES};
-use crate::image_convert::{self, modify_url};
-use crate::{BUILD_DIR, MINIFY};
+use crate::authors::{self, Author};
+use crate::cache::{self, BuildCache, CachedPost};
+use crate::config::{Section, SiteConfig, SortOrder, Taxonomy, MAIN_SECTION, RESERVED_NAMES};
+use crate::error::{Error, Result};
+use crate::html::{self, minify_css, minify_html};
+use crate::image_convert;
+use crate::markdown::{self, Rendered};
+use crate::page::StaticPage;
+use crate::post::{is_bundle_index, Post, BUNDLE_INDEX};
+use crate::rss;
+use crate::site::Site;
+use crate::sitemap;
+use chrono::{Local, NaiveDate};
+use globset::GlobSet;
+use itertools::Itertools;
 use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
-use std::collections::HashMap;
-use std::fs::DirEntry;
-use std::path::PathBuf;
+use serde::Serialize;
+use slug::slugify;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
 use std::{fs, io};
 use tera::Context;
 
-pub fn create_folder(path: &str) {
-    match fs::create_dir(path) {
-        Ok(_) => (),
+pub const INDEX_TEMPLATE: &str = "index.html";
+pub const ABOUT_TEMPLATE: &str = "about.html";
+pub const NOT_FOUND_TEMPLATE: &str = "404.html";
+pub const ALL_TAGS_TEMPLATE: &str = "all-tags.html";
+/// List of the terms of a taxonomy
+pub const ALL_TERMS_TEMPLATE: &str = "all-terms.html";
+pub const AUTHOR_TEMPLATE: &str = "author.html";
+/// Template of standalone pages that do not set `template:`
+pub const PAGE_TEMPLATE: &str = "page.html";
+pub const FEED_PATH: &str = "rss.xml";
+pub const SITEMAP_PATH: &str = "sitemap.xml";
+/// Number of posts in the recently updated list of the home page
+pub const RECENTLY_UPDATED: usize = 5;
+/// Redirects of post aliases for the hosting provider
+pub const REDIRECTS_PATH: &str = "_redirects";
+/// Url prefix of converted images
+pub const IMAGES_PATH: &str = "/images/";
+
+/// Image source file -> scale factor requested with `{width=x%}`.
+pub type ImageScales = HashMap<PathBuf, f64>;
+
+/// A rendered html page, `path` is relative to the build folder.
+pub struct Page {
+    pub path: PathBuf,
+    pub html: String,
+}
+
+pub fn create_folder(path: &str) -> Result<()> {
+    match fs::create_dir_all(path) {
+        Ok(_) => Ok(()),
         Err(err) => match err.kind() {
-            io::ErrorKind::AlreadyExists => (),
-            _ => panic!("Could not create folder {}", path),
+            io::ErrorKind::AlreadyExists => Ok(()),
+            _ => Err(Error::io(Path::new(path), err)),
         },
     }
 }
 
-pub fn init_build(clean_build: bool) {
-    if clean_build {
-        fs::remove_dir_all("build").expect("deleted build dir");
+/// Delete the build folder and the build cache, returns false if there was nothing to delete.
+pub fn clean(config: &SiteConfig) -> io::Result<bool> {
+    let cache_removed = match fs::remove_file(&config.cache_file) {
+        Ok(_) => true,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => false,
+        Err(err) => return Err(err),
+    };
+    match fs::remove_dir_all(&config.build_dir) {
+        Ok(_) => Ok(true),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(cache_removed),
+        Err(err) => Err(err),
     }
+}
 
-    create_folder(BUILD_DIR);
-    create_folder(&format!("{BUILD_DIR}/images"));
-    create_folder(&format!("{BUILD_DIR}/posts"));
-    create_folder(&format!("{BUILD_DIR}/tags"));
-    create_folder(&format!("{BUILD_DIR}/fonts"));
+pub fn init_build(config: &SiteConfig) -> Result<()> {
+    let build_dir = &config.build_dir;
+    if config.clean_build {
+        clean(config).map_err(|e| Error::io(Path::new(build_dir), e))?;
+    }
+
+    // subfolders are created along with the files written to them
+    create_folder(build_dir)
 }
 
-pub fn build_html(name: &str, template_name: &str, path: &str, context: Context) {
-    let mut html = TEMPLATES
-        .render(template_name, &context)
-        .expect(&format!("{template_name} render"));
+/// Context with the site wide variables every template can use.
+pub fn base_context(site: &Site) -> Context {
+    let mut context = Context::new();
+    context.insert("url", &site.config.url);
+    context.insert("blog_name", &site.config.name);
+    context.insert("menu", &menu(site.pages()));
+    context
+}
 
-    if MINIFY {
-        html = minify_html(&html);
-    }
-    let mut path: String = path.to_string();
+/// Output path of a page: `{path}{name}/index.html`, or the root `index.html` for `index`.
+pub fn page_path(name: &str, path: &str) -> PathBuf {
     if name != "index" {
-        path = format!("{}{}{}/", BUILD_DIR, path, name);
-        create_folder(&path);
+        PathBuf::from(format!("{}{}/index.html", path.trim_start_matches('/'), name))
+    } else {
+        PathBuf::from("index.html")
+    }
+}
+
+/// Output path of a site relative url: `/a/b/` is `a/b/index.html`, `/a/b.html` is `a/b.html`.
+pub fn url_path(url: &str) -> PathBuf {
+    let path = url.trim_start_matches('/');
+    if path.is_empty() || url.ends_with('/') || Path::new(path).extension().is_none() {
+        Path::new(path).join("index.html")
+    } else {
+        PathBuf::from(path)
+    }
+}
+
+/// Render a template, minified if enabled.
+pub fn render_template(site: &Site, template_name: &str, context: &Context) -> Result<String> {
+    let html = site.templates().render(template_name, context).map_err(|e| Error::Template {
+        name: template_name.to_string(),
+        message: html::tera_error(&e),
+    })?;
+
+    if site.config.minify {
+        Ok(minify_html(&html))
+    } else {
+        Ok(html)
+    }
+}
+
+pub fn render_page(
+    site: &Site,
+    name: &str,
+    template_name: &str,
+    path: &str,
+    context: Context,
+) -> Result<Page> {
+    let html = render_template(site, template_name, &context)?;
+    Ok(Page { path: page_path(name, path), html })
+}
+
+pub fn write_pages(config: &SiteConfig, cache: &BuildCache, pages: &[Page]) -> Result<()> {
+    for page in pages {
+        cache
+            .write(&config.build_dir, &page.path, page.html.as_bytes())
+            .map_err(|e| Error::io(&page.path, e))?;
+    }
+    Ok(())
+}
+
+/// Posts anywhere under the section folders in path order: markdown files and the `index.md` of
+/// page bundles, whose other files are not posts. `SiteConfig::images_dir` and ignored files are skipped.
+/// Folders of `[[sections]]` that do not exist yet have no posts.
+pub fn find_posts(config: &SiteConfig) -> Result<Vec<PathBuf>> {
+    let ignore = config.ignore_globs();
+    let mut posts = Vec::new();
+    for section in config.all_sections() {
+        let dir = Path::new(&section.dir);
+        if section.name != MAIN_SECTION && !dir.is_dir() {
+            continue;
+        }
+        visit_posts(config, &ignore, dir, dir, &mut posts).map_err(|e| Error::io(dir, e))?;
+    }
+    Ok(posts)
+}
+
+/// Entries of a folder, sorted so builds do not depend on the file system order.
+fn sorted_entries(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)?.map(|result| result.map(|entry| entry.path())).collect::<io::Result<_>>()?;
+    entries.sort();
+    Ok(entries)
+}
+
+fn visit_posts(config: &SiteConfig, ignore: &GlobSet, root: &Path, dir: &Path, posts: &mut Vec<PathBuf>) -> io::Result<()> {
+    let bundle_index = dir.join(BUNDLE_INDEX);
+    if dir != root && bundle_index.is_file() {
+        posts.push(bundle_index);
+        return Ok(());
+    }
+    for path in sorted_entries(dir)? {
+        if config.is_ignored(ignore, &path) || path == Path::new(&config.images_dir) {
+            continue;
+        }
+        if path.is_dir() {
+            visit_posts(config, ignore, root, &path, posts)?;
+        } else if path.extension().unwrap_or_default() == "md" {
+            posts.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Whether a file under a section or the images folder is ignored itself or in an ignored folder.
+pub fn is_ignored(config: &SiteConfig, path: &Path) -> bool {
+    let ignore = config.ignore_globs();
+    let sections = config.all_sections();
+    let mut roots: Vec<&Path> = sections.iter().map(|section| Path::new(&section.dir)).collect();
+    roots.push(Path::new(&config.images_dir));
+    if !roots.iter().any(|root| path.starts_with(root)) {
+        return false;
+    }
+    path.ancestors()
+        .take_while(|a| !roots.contains(a) && !a.as_os_str().is_empty())
+        .any(|a| config.is_ignored(&ignore, a))
+}
+
+/// Whether `path` is a post, see `find_posts`. Used for changed files, it does not need to exist.
+pub fn is_post_path(config: &SiteConfig, path: &Path) -> bool {
+    let section = config.section_of(path);
+    let posts_dir = Path::new(&section.dir);
+    let is_markdown = path.extension().unwrap_or_default() == "md";
+    if !is_markdown || !path.starts_with(posts_dir) || path.starts_with(&config.images_dir) || is_ignored(config, path) {
+        return false;
+    }
+    // files in a bundle other than its index.md
+    path.ancestors()
+        .skip(1)
+        .take_while(|dir| *dir != posts_dir)
+        .map(|dir| dir.join(BUNDLE_INDEX))
+        .all(|index| index == path || !is_bundle_index(config, &index) || !index.is_file())
+}
+
+pub fn load_post(config: &SiteConfig, path: &Path) -> Result<Post> {
+    Post::load(path, config)
+}
+
+/// Whether a post is part of this build. Drafts are skipped unless `config.drafts` is set,
+/// posts dated in the future unless `config.future` is set.
+pub fn is_published(config: &SiteConfig, post: &Post) -> bool {
+    (!post.metadata.draft || config.drafts) && (config.future || !post.metadata.date.is_after(Local::now()))
+}
+
+/// Load every published post, the errors of all invalid posts are reported together.
+pub fn load_posts(config: &SiteConfig) -> Result<Vec<Post>> {
+    let (posts, errors) = load_posts_with_errors(config)?;
+    Error::collect(errors)?;
+    Ok(posts)
+}
+
+/// Load every published post, returns the posts that loaded and the errors of the others.
+pub fn load_posts_with_errors(config: &SiteConfig) -> Result<(Vec<Post>, Vec<Error>)> {
+    let mut posts = Vec::new();
+    let mut errors = Vec::new();
+    for path in find_posts(config)? {
+        match load_post(config, &path) {
+            Ok(post) if is_published(config, &post) => match check_urls(&post, &posts) {
+                Ok(()) => posts.push(post),
+                Err(e) => errors.push(e),
+            },
+            Ok(post) if post.metadata.draft => log::info!("Skipping draft: {}", post.metadata.title),
+            Ok(post) => log::info!("Skipping scheduled post: {} ({})", post.metadata.title, post.metadata.date),
+            Err(e) => errors.push(e),
+        }
+    }
+    Ok((posts, errors))
+}
+
+/// Errors of rendering the markdown of `posts`, nothing is cached or written.
+pub fn render_errors(config: &SiteConfig, posts: &[Post]) -> Vec<Error> {
+    posts.par_iter().filter_map(|post| post.render(config).err()).collect()
+}
+
+/// Permalink and aliases of a post, the urls it is written to.
+fn post_urls(post: &Post) -> impl Iterator<Item = &str> {
+    std::iter::once(post.metadata.permalink.as_str()).chain(post.metadata.aliases.iter().map(String::as_str))
+}
+
+/// Error if the permalink or an alias of `post` is already used by one of `others`.
+pub fn check_urls<'a>(post: &Post, others: impl IntoIterator<Item = &'a Post>) -> Result<()> {
+    for other in others {
+        if let Some(url) = post_urls(post).find(|url| post_urls(other).any(|u| u == *url)) {
+            let message = format!("url {url} is already used by {}", other.path.display());
+            return Err(Error::source(&post.path, "", None, message));
+        }
+    }
+    Ok(())
+}
+
+/// Warn when the text of a published post changed since the last build but its `updated` date did not.
+/// Not for watched sites, which rebuild on every save of a post being written.
+fn warn_not_updated(cache: &BuildCache, post: &Post, contents: u64) {
+    let Some(previous) = cache.previous_post(&post.metadata.permalink) else {
+        return;
+    };
+    let changed = previous.contents != 0 && previous.contents != contents;
+    if changed && !post.metadata.draft && previous.updated == post.metadata.updated {
+        log::warn!("{} changed without a new `updated` date", post.path.display());
+    }
+}
+
+/// Render the markdown of a post, or take it from the cache if nothing it depends on changed.
+fn render_markdown(site: &Site, cache: &BuildCache, post: &Post) -> Result<CachedPost> {
+    let config = &site.config;
+    let permalink = &post.metadata.permalink;
+    let updated = post.metadata.updated;
+    let contents = cache::hash(&post.contents);
+    // relative urls of bundles resolve against the post's folder, links only to files it has
+    let hash = cache::hash(&(&post.contents, &post.path, bundle_files(config, post)?, cache::hash_config(config)));
+
+    let cached = match cache.post(permalink, hash) {
+        Some(cached) => CachedPost { updated, ..cached },
+        None => {
+            log::info!("Processing: {}", post.metadata.title);
+            if !site.is_watched() {
+                warn_not_updated(cache, post, contents);
+            }
+            let rendered = post.render(config)?;
+            let images = rendered
+                .images
+                .iter()
+                .map(|path| (path.clone(), cache::hash_file(path)))
+                .collect();
+            CachedPost { hash, contents, updated, images, rendered }
+        }
+    };
+    cache.insert_post(permalink, cached.clone());
+    Ok(cached)
+}
+
+/// A part in the series box of a post.
+#[derive(Serialize)]
+pub struct SeriesPart<'a> {
+    pub number: usize,
+    pub title: &'a str,
+    pub permalink: &'a str,
+    pub current: bool,
+}
+
+/// Series box of a post: the series and all of its parts.
+#[derive(Serialize)]
+pub struct SeriesContext<'a> {
+    pub name: &'a str,
+    pub permalink: String,
+    pub parts: Vec<SeriesPart<'a>>,
+}
+
+/// Link to another post for navigation.
+#[derive(Serialize)]
+pub struct PostLink<'a> {
+    pub title: &'a str,
+    pub permalink: &'a str,
+    pub date: NaiveDate,
+}
+
+impl<'a> From<&'a Post> for PostLink<'a> {
+    fn from(post: &'a Post) -> PostLink<'a> {
+        PostLink { title: &post.metadata.title, permalink: &post.metadata.permalink, date: post.metadata.date.date_naive() }
+    }
+}
+
+/// Previous and next post within a tag.
+#[derive(Serialize)]
+pub struct TagNavigation<'a> {
+    pub tag: &'a str,
+    pub prev: Option<PostLink<'a>>,
+    pub next: Option<PostLink<'a>>,
+}
+
+/// The posts published right before and right after `post`.
+pub fn neighbours<'a>(posts: impl IntoIterator<Item = &'a Post>, post: &Post) -> (Option<&'a Post>, Option<&'a Post>) {
+    let mut sorted: Vec<&Post> = posts.into_iter().collect();
+    sorted.sort_by_key(|p| (p.metadata.date, &p.metadata.slug));
+    match sorted.iter().position(|p| p.metadata.permalink == post.metadata.permalink) {
+        Some(i) => (i.checked_sub(1).map(|i| sorted[i]), sorted.get(i + 1).copied()),
+        None => (None, None),
+    }
+}
+
+/// Neighbours of `post` among the posts sharing each of its tags.
+pub fn tag_neighbours<'a>(posts: &'a [Post], post: &'a Post) -> Vec<TagNavigation<'a>> {
+    post.metadata
+        .tags
+        .iter()
+        .map(|tag| {
+            let (prev, next) = neighbours(posts.iter().filter(|p| p.metadata.tags.contains(tag)), post);
+            TagNavigation { tag, prev: prev.map(PostLink::from), next: next.map(PostLink::from) }
+        })
+        .filter(|nav| nav.prev.is_some() || nav.next.is_some())
+        .collect()
+}
+
+fn series_context<'a>(name: &'a str, parts: &'a [Post], current: &Post) -> SeriesContext<'a> {
+    SeriesContext {
+        name,
+        permalink: series_permalink(name),
+        parts: parts
+            .iter()
+            .zip(html::part_numbers(parts))
+            .map(|(p, number)| SeriesPart {
+                number,
+                title: &p.metadata.title,
+                permalink: &p.metadata.permalink,
+                current: p.path == current.path && p.metadata.slug == current.metadata.slug,
+            })
+            .collect(),
+    }
+}
+
+/// Template of a post: its `template:`, the template of its first tag that has one, the template
+/// of its section or `config.post_template`.
+pub fn post_template<'a>(config: &'a SiteConfig, post: &'a Post) -> &'a str {
+    post.metadata
+        .template
+        .as_deref()
+        .or_else(|| post.metadata.tags.iter().find_map(|tag| config.tag_templates.get(tag)).map(String::as_str))
+        .or_else(|| config.sections.iter().find(|s| s.name == post.section).and_then(|s| s.template.as_deref()))
+        .unwrap_or(&config.post_template)
+}
+
+/// Error for the source file `path` if the template it asks for does not exist.
+fn check_template(site: &Site, path: &Path, template: &str) -> Result<()> {
+    if site.templates().get_template_names().any(|name| name == template) {
+        return Ok(());
+    }
+    let message = format!("template {template:?} does not exist in {}", site.config.templates_dir);
+    Err(Error::source(path, "", None, message))
+}
+
+/// Render a post page, also returns the rendered markdown.
+/// `posts` are all posts of the build, the navigation links to them, and `all_series` their `series`
+/// for the series box.
+pub fn render_post(
+    site: &Site,
+    cache: &BuildCache,
+    post: &Post,
+    posts: &[Post],
+    all_series: &BTreeMap<&str, Vec<Post>>,
+) -> Result<(Page, Rendered)> {
+    let config = &site.config;
+    let template = post_template(config, post);
+    check_template(site, &post.path, template)?;
+    let rendered = render_markdown(site, cache, post)?.rendered;
+
+    let mut context = base_context(site);
+    context.insert("title", &post.metadata.title);
+    context.insert("summary", &post.summary);
+    context.insert("content", &rendered.html);
+    context.insert("has_katex", &rendered.has_katex);
+    context.insert("word_count", &rendered.word_count);
+    context.insert("reading_time", &markdown::reading_time(rendered.word_count));
+    context.insert("date", &post.metadata.date.date_naive());
+    context.insert("updated", &post.metadata.updated.map(|d| d.date_naive()));
+    context.insert("tags", &post.metadata.tags);
+    context.insert("authors", &authors::post_authors(site.authors(), post));
+    let post_series = post.metadata.series.as_deref().and_then(|name| all_series.get_key_value(name));
+    context.insert("series", &post_series.map(|(name, parts)| series_context(name, parts, post)));
+    let (prev, next) = neighbours(posts.iter().filter(|p| p.section == post.section), post);
+    context.insert("section", &post.section);
+    context.insert("prev", &prev.map(PostLink::from));
+    context.insert("next", &next.map(PostLink::from));
+    context.insert("tag_navigation", &tag_neighbours(posts, post));
+    context.insert("taxonomies", &post_terms(post));
+    context.insert("draft", &post.metadata.draft);
+    context.insert("extra", &post.metadata.extra);
+
+    let html = render_template(site, template, &context)?;
+    let page = Page { path: url_path(&post.metadata.permalink), html };
+    Ok((page, rendered))
+}
+
+/// Redirect pages from the aliases of a post to its permalink.
+pub fn render_aliases(post: &Post) -> Vec<Page> {
+    post.metadata
+        .aliases
+        .iter()
+        .map(|alias| Page { path: url_path(alias), html: html::create_redirect(&post.metadata.url) })
+        .collect()
+}
+
+/// `_redirects` file with a permanent redirect for every alias.
+pub fn render_redirects(posts: &[Post]) -> String {
+    let mut redirects = String::new();
+    for post in posts {
+        for alias in &post.metadata.aliases {
+            redirects.push_str(&format!("{} {} 301\n", alias, post.metadata.permalink));
+        }
+    }
+    redirects
+}
+
+pub fn write_redirects(config: &SiteConfig, cache: &BuildCache, posts: &[Post]) -> Result<()> {
+    let redirects = render_redirects(posts);
+    if redirects.is_empty() {
+        cache.forget(Path::new(REDIRECTS_PATH));
+        return Ok(());
+    }
+    cache
+        .write(&config.build_dir, Path::new(REDIRECTS_PATH), redirects.as_bytes())
+        .map_err(|e| Error::io(Path::new(REDIRECTS_PATH), e))
+}
+
+pub fn render_posts(
+    site: &Site,
+    cache: &BuildCache,
+    posts: &[Post],
+) -> Result<(Vec<Page>, Vec<Rendered>)> {
+    let all_series = series(posts);
+    let results: Vec<Result<(Page, Rendered)>> =
+        posts.par_iter().map(|p| render_post(site, cache, p, posts, &all_series)).collect();
+
+    let mut rendered = Vec::new();
+    let mut errors = Vec::new();
+    for result in results {
+        match result {
+            Ok(r) => rendered.push(r),
+            Err(e) => errors.push(e),
+        }
+    }
+    Error::collect(errors)?;
+    Ok(rendered.into_iter().unzip())
+}
+
+/// Copy of `posts` with the word counts of their rendered markdown.
+pub fn with_word_counts(posts: &[Post], rendered: &[Rendered]) -> Vec<Post> {
+    posts
+        .iter()
+        .zip(rendered)
+        .map(|(post, rendered)| {
+            let mut post = post.clone();
+            post.set_word_count(rendered);
+            post
+        })
+        .collect()
+}
+
+/// Posts of an index page, newest first.
+fn newest_first(posts: &[Post]) -> Vec<&Post> {
+    html::sort_posts(posts, SortOrder::Newest)
+}
+
+/// Image scales requested by all posts.
+pub fn image_scales(rendered: &[Rendered]) -> ImageScales {
+    let mut image_scale: ImageScales = HashMap::new();
+    for r in rendered {
+        image_scale.extend(r.image_scale.clone());
+    }
+    image_scale
+}
+
+/// Posts grouped by tag.
+pub fn tags(posts: &[Post]) -> BTreeMap<&str, Vec<Post>> {
+    let mut unique_tags: BTreeMap<&str, Vec<Post>> = BTreeMap::new();
+    for post in posts {
+        for tag in &post.metadata.tags {
+            unique_tags.entry(tag).or_default().push(post.clone());
+        }
+    }
+    unique_tags
+}
+
+/// Posts grouped by section name, only sections with posts are included.
+pub fn sections(posts: &[Post]) -> BTreeMap<&str, Vec<Post>> {
+    let mut sections: BTreeMap<&str, Vec<Post>> = BTreeMap::new();
+    for post in posts {
+        sections.entry(&post.section).or_default().push(post.clone());
+    }
+    sections
+}
+
+/// Posts of the sections listed on the home page.
+pub fn home_posts(config: &SiteConfig, posts: &[Post]) -> Vec<Post> {
+    let home: Vec<String> = config.all_sections().into_iter().filter(|s| s.home).map(|s| s.name).collect();
+    posts.iter().filter(|p| home.contains(&p.section)).cloned().collect()
+}
+
+/// Posts of the sections included in the main feed.
+pub fn feed_posts(config: &SiteConfig, posts: &[Post]) -> Vec<Post> {
+    let feed: Vec<String> = config.all_sections().into_iter().filter(|s| s.feed).map(|s| s.name).collect();
+    posts.iter().filter(|p| feed.contains(&p.section)).cloned().collect()
+}
+
+pub fn section_permalink(name: &str) -> String {
+    format!("/{name}/")
+}
+
+pub fn section_feed_path(name: &str) -> PathBuf {
+    Path::new(name).join(FEED_PATH)
+}
+
+/// Index page of a `[[sections]]` section, in its sort order.
+pub fn render_section(site: &Site, section: &Section, section_posts: &[Post]) -> Result<Page> {
+    let mut context = base_context(site);
+    context.insert("content", &html::create_section_index(section_posts, section.sort));
+    context.insert("posts", &html::sort_posts(section_posts, section.sort));
+    context.insert("title", &section.name);
+    context.insert("index_title", &section.name);
+    context.insert("section", &section.name);
+    context.insert("feed", &format!("/{}", section_feed_path(&section.name).display()));
+    let html = render_template(site, INDEX_TEMPLATE, &context)?;
+    Ok(Page { path: url_path(&section_permalink(&section.name)), html })
+}
+
+/// Posts grouped by author id, only authors with posts are included.
+pub fn author_posts(posts: &[Post]) -> BTreeMap<&str, Vec<Post>> {
+    let mut authors: BTreeMap<&str, Vec<Post>> = BTreeMap::new();
+    for post in posts {
+        for author in &post.metadata.authors {
+            authors.entry(author).or_default().push(post.clone());
+        }
+    }
+    authors
+}
+
+/// Posts grouped by series, ordered by part number and then by date.
+pub fn series(posts: &[Post]) -> BTreeMap<&str, Vec<Post>> {
+    let mut series: BTreeMap<&str, Vec<Post>> = BTreeMap::new();
+    for post in posts {
+        if let Some(name) = &post.metadata.series {
+            series.entry(name).or_default().push(post.clone());
+        }
+    }
+    for parts in series.values_mut() {
+        parts.sort_by_key(|p| (p.metadata.part.unwrap_or(u32::MAX), p.metadata.date));
+    }
+    series
+}
+
+pub fn series_permalink(name: &str) -> String {
+    format!("/series/{}/", slugify(name))
+}
+
+pub fn render_series(site: &Site, name: &str, parts: &[Post]) -> Result<Page> {
+    let mut context = base_context(site);
+    context.insert("content", &html::create_series_index(parts));
+    context.insert("title", name);
+    context.insert("index_title", name);
+    let html = render_template(site, INDEX_TEMPLATE, &context)?;
+    Ok(Page { path: url_path(&series_permalink(name)), html })
+}
+
+pub fn author_page_path(author: &Author) -> PathBuf {
+    url_path(&author.permalink)
+}
+
+pub fn render_author(site: &Site, author: &Author, author_posts: &[Post]) -> Result<Page> {
+    let index_content = html::create_index(author_posts);
+    let mut context = base_context(site);
+    context.insert("content", &index_content);
+    context.insert("posts", &newest_first(author_posts));
+    context.insert("title", &author.name);
+    context.insert("author", author);
+    let html = render_template(site, AUTHOR_TEMPLATE, &context)?;
+    Ok(Page { path: author_page_path(author), html })
+}
+
+pub fn tag_page_path(tag: &str) -> PathBuf {
+    page_path(&slugify(tag), "/tags/")
+}
+
+pub fn render_index(site: &Site, posts: &[Post]) -> Result<Page> {
+    let posts = &home_posts(&site.config, posts);
+    let index_content = html::create_index(posts);
+    let mut context = base_context(site);
+    context.insert("content", &index_content);
+    context.insert("posts", &newest_first(posts));
+    context.insert("recently_updated", &html::create_recently_updated(posts, RECENTLY_UPDATED));
+    context.insert("title", &site.config.name);
+    render_page(site, "index", INDEX_TEMPLATE, "/", context)
+}
+
+pub fn render_about(site: &Site) -> Result<Page> {
+    let mut context = base_context(site);
+    context.insert("title", &site.config.name);
+    context.insert("authors", &site.authors().values().collect::<Vec<_>>());
+    render_page(site, "about", ABOUT_TEMPLATE, "/", context)
+}
+
+/// Whether a page with the slug `about` replaces the about template.
+pub fn about_overridden(site: &Site) -> bool {
+    site.pages().iter().any(|page| page.url() == "/about/")
+}
+
+/// Not found page, served by the hosting provider.
+pub fn render_not_found(site: &Site) -> Result<Page> {
+    let mut context = base_context(site);
+    context.insert("title", "Not found");
+    let mut not_found = render_page(site, "404", NOT_FOUND_TEMPLATE, "/", context)?;
+    not_found.path = PathBuf::from("404.html");
+    Ok(not_found)
+}
+
+/// Link to a term page of a taxonomy.
+#[derive(Serialize)]
+pub struct TermLink<'a> {
+    pub name: &'a str,
+    pub permalink: String,
+}
+
+/// Entry of the list of terms of a taxonomy.
+#[derive(Serialize)]
+pub struct TermEntry<'a> {
+    pub name: &'a str,
+    pub permalink: String,
+    /// Number of posts with the term
+    pub count: usize,
+}
+
+/// Posts grouped by their terms of the taxonomy `name`.
+pub fn taxonomy_terms<'a>(posts: &'a [Post], name: &str) -> BTreeMap<&'a str, Vec<Post>> {
+    let mut terms: BTreeMap<&str, Vec<Post>> = BTreeMap::new();
+    for post in posts {
+        for term in post.metadata.taxonomies.get(name).into_iter().flatten() {
+            terms.entry(term).or_default().push(post.clone());
+        }
+    }
+    terms
+}
+
+pub fn taxonomy_permalink(taxonomy: &str) -> String {
+    format!("/{taxonomy}/")
+}
+
+pub fn term_permalink(taxonomy: &str, term: &str) -> String {
+    format!("/{taxonomy}/{}/", slugify(term))
+}
+
+pub fn term_feed_path(taxonomy: &str, term: &str) -> PathBuf {
+    url_path(&term_permalink(taxonomy, term)).with_file_name(FEED_PATH)
+}
+
+/// Taxonomy name -> links to the terms of `post`, for the post page.
+fn post_terms(post: &Post) -> BTreeMap<&str, Vec<TermLink<'_>>> {
+    post.metadata
+        .taxonomies
+        .iter()
+        .map(|(name, terms)| {
+            let links = terms.iter().map(|term| TermLink { name: term, permalink: term_permalink(name, term) });
+            (name.as_str(), links.collect())
+        })
+        .collect()
+}
+
+/// Index of the posts with `term`.
+pub fn render_term(site: &Site, taxonomy: &Taxonomy, term: &str, term_posts: &[Post]) -> Result<Page> {
+    let mut context = base_context(site);
+    context.insert("content", &html::create_index(term_posts));
+    context.insert("posts", &newest_first(term_posts));
+    context.insert("title", term);
+    context.insert("index_title", term);
+    context.insert("taxonomy", &taxonomy.name);
+    context.insert("term", term);
+    if taxonomy.feeds {
+        context.insert("feed", &format!("/{}", term_feed_path(&taxonomy.name, term).display()));
+    }
+    let html = render_template(site, INDEX_TEMPLATE, &context)?;
+    Ok(Page { path: url_path(&term_permalink(&taxonomy.name, term)), html })
+}
+
+/// List of every term of a taxonomy with its post count.
+pub fn render_all_terms(site: &Site, taxonomy: &Taxonomy, posts: &[Post]) -> Result<Page> {
+    let terms: Vec<TermEntry> = taxonomy_terms(posts, &taxonomy.name)
+        .into_iter()
+        .map(|(term, term_posts)| TermEntry {
+            name: term,
+            permalink: term_permalink(&taxonomy.name, term),
+            count: term_posts.len(),
+        })
+        .collect();
+    let mut context = base_context(site);
+    context.insert("taxonomy", &taxonomy.name);
+    context.insert("terms", &terms);
+    context.insert("title", &taxonomy.name);
+    let html = render_template(site, ALL_TERMS_TEMPLATE, &context)?;
+    Ok(Page { path: url_path(&taxonomy_permalink(&taxonomy.name)), html })
+}
+
+pub fn render_tag(site: &Site, tag: &str, tag_posts: &[Post]) -> Result<Page> {
+    let index_content = html::create_index(tag_posts);
+    let mut context = base_context(site);
+    context.insert("content", &index_content);
+    context.insert("posts", &newest_first(tag_posts));
+    context.insert("title", &tag);
+    context.insert("index_title", &tag);
+
+    render_page(site, &slugify(tag), INDEX_TEMPLATE, "/tags/", context)
+}
+
+pub fn render_all_tags(site: &Site, posts: &[Post]) -> Result<Page> {
+    let all_tags: Vec<&str> = tags(posts).into_keys().collect();
+    let mut context = base_context(site);
+    context.insert("tags", &all_tags);
+    context.insert("title", "All tags");
+    render_page(site, "all-tags", ALL_TAGS_TEMPLATE, "/tags/", context)
+}
+
+/// Home page, about page, 404 page and the tag indices.
+pub fn render_indices(site: &Site, posts: &[Post]) -> Result<Vec<Page>> {
+    let mut pages = vec![render_index(site, posts)?, render_not_found(site)?];
+    if !about_overridden(site) {
+        pages.push(render_about(site)?);
+    }
+    for (tag, tag_posts) in tags(posts) {
+        pages.push(render_tag(site, tag, &tag_posts)?);
+    }
+    pages.push(render_all_tags(site, posts)?);
+    for taxonomy in &site.config.taxonomies {
+        for (term, term_posts) in taxonomy_terms(posts, &taxonomy.name) {
+            pages.push(render_term(site, taxonomy, term, &term_posts)?);
+        }
+        pages.push(render_all_terms(site, taxonomy, posts)?);
+    }
+    for (name, parts) in series(posts) {
+        pages.push(render_series(site, name, &parts)?);
+    }
+    let sections = sections(posts);
+    for section in &site.config.sections {
+        let section_posts = sections.get(section.name.as_str()).map(Vec::as_slice).unwrap_or_default();
+        pages.push(render_section(site, section, section_posts)?);
+    }
+    for (id, author_posts) in author_posts(posts) {
+        if let Some(author) = site.authors().get(id) {
+            pages.push(render_author(site, author, &author_posts)?);
+        }
+    }
+    Ok(pages)
+}
+
+/// Markdown files of the pages folder, a missing folder has none.
+pub fn find_pages(config: &SiteConfig) -> Result<Vec<PathBuf>> {
+    let pages_dir = Path::new(&config.pages_dir);
+    if !pages_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut pages = sorted_entries(pages_dir).map_err(|e| Error::io(pages_dir, e))?;
+    pages.retain(|p| p.is_file() && is_page_path(config, p));
+    Ok(pages)
+}
+
+/// Whether `path` is a page, see `find_pages`. Used for changed files, it does not need to exist.
+pub fn is_page_path(config: &SiteConfig, path: &Path) -> bool {
+    path.parent() == Some(Path::new(&config.pages_dir))
+        && path.extension().unwrap_or_default() == "md"
+        && !config.is_ignored(&config.ignore_globs(), path)
+}
+
+/// Load every page, pages with the same url are an error.
+pub fn load_pages(config: &SiteConfig, posts: &[Post]) -> Result<Vec<StaticPage>> {
+    let mut pages: Vec<StaticPage> = Vec::new();
+    let mut errors = Vec::new();
+    for path in find_pages(config)? {
+        match StaticPage::load(&path) {
+            Ok(page) => match pages.iter().find(|p| p.url() == page.url()) {
+                Some(other) => {
+                    let message = format!("url {} is already used by {}", page.url(), other.path.display());
+                    errors.push(Error::source(&path, "", None, message));
+                }
+                None => match check_page_url(config, &page, posts) {
+                    Ok(()) => pages.push(page),
+                    Err(e) => errors.push(e),
+                },
+            },
+            Err(e) => errors.push(e),
+        }
+    }
+    Error::collect(errors)?;
+    Ok(pages)
+}
+
+/// Error if the url of `page` is used by the blog: a reserved name, the index of a section or
+/// taxonomy, or a permalink or alias of one of `posts`. `about` is allowed, see `about_overridden`.
+pub fn check_page_url(config: &SiteConfig, page: &StaticPage, posts: &[Post]) -> Result<()> {
+    let slug = page.metadata.slug.as_str();
+    let url = page.url();
+    let message = if slug != "about" && RESERVED_NAMES.contains(&slug) {
+        format!("page slug {slug:?} is reserved, {url} is used by the blog")
+    } else if config.sections.iter().any(|section| section.name == slug) {
+        format!("url {url} is already used by the index of section {slug:?}")
+    } else if config.taxonomies.iter().any(|taxonomy| taxonomy.name == slug) {
+        format!("url {url} is already used by the index of taxonomy {slug:?}")
+    } else if let Some(post) = posts.iter().find(|post| post_urls(post).any(|u| url_path(u) == url_path(&url))) {
+        format!("url {url} is already used by {}", post.path.display())
     } else {
-        path = format!("{}/", BUILD_DIR);
+        return Ok(());
+    };
+    Err(Error::source(&page.path, "", None, message))
+}
+
+/// Link of the navigation menu.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct MenuItem {
+    pub title: String,
+    pub url: String,
+}
+
+/// Navigation menu: the pages with a `menu` position, in that order.
+pub fn menu(pages: &[StaticPage]) -> Vec<MenuItem> {
+    pages
+        .iter()
+        .filter_map(|page| Some((page.metadata.menu?, page)))
+        .sorted_by_key(|(position, page)| (*position, page.metadata.slug.clone()))
+        .map(|(_, page)| MenuItem { title: page.metadata.title.clone(), url: page.url() })
+        .collect()
+}
+
+/// Template of a page: the one of its front matter, or `PAGE_TEMPLATE`.
+pub fn page_template(page: &StaticPage) -> &str {
+    page.metadata.template.as_deref().unwrap_or(PAGE_TEMPLATE)
+}
+
+/// Render a standalone page, also returns the rendered markdown.
+pub fn render_static_page(site: &Site, page: &StaticPage) -> Result<(Page, Rendered)> {
+    let template = page_template(page);
+    check_template(site, &page.path, template)?;
+    let rendered = page.render(&site.config)?;
+
+    let mut context = base_context(site);
+    context.insert("title", &page.metadata.title);
+    context.insert("summary", &page.metadata.description.as_deref().unwrap_or_default());
+    context.insert("content", &rendered.html);
+    context.insert("has_katex", &rendered.has_katex);
+    context.insert("extra", &page.metadata.extra);
+    let html = render_template(site, template, &context)?;
+    Ok((Page { path: url_path(&page.url()), html }, rendered))
+}
+
+/// Render every standalone page, the errors of all failed pages are reported together.
+pub fn render_static_pages(site: &Site) -> Result<(Vec<Page>, Vec<Rendered>)> {
+    let mut rendered = Vec::new();
+    let mut errors = Vec::new();
+    for page in site.pages() {
+        match render_static_page(site, page) {
+            Ok(r) => rendered.push(r),
+            Err(e) => errors.push(e),
+        }
     }
+    Error::collect(errors)?;
+    Ok(rendered.into_iter().unzip())
+}
+
+/// Main feed, with the posts of the sections that have `feed` set.
+pub fn render_feed(site: &Site, posts: &[Post]) -> String {
+    rss::generate_rss(&feed_posts(&site.config, posts), site.authors(), &site.config.name, &site.config.url)
+}
+
+pub fn write_feed(site: &Site, cache: &BuildCache, posts: &[Post]) -> io::Result<()> {
+    let rss_xml = render_feed(site, posts);
+    cache.write(&site.config.build_dir, Path::new(FEED_PATH), rss_xml.as_bytes())
+}
+
+/// Feed of the posts with a term of a taxonomy.
+pub fn write_term_feed(site: &Site, cache: &BuildCache, taxonomy: &str, term: &str, term_posts: &[Post]) -> Result<()> {
+    let path = term_feed_path(taxonomy, term);
+    let name = format!("{} - {}", site.config.name, term);
+    let rss_xml = rss::generate_rss(term_posts, site.authors(), &name, &site.config.url);
+    cache.write(&site.config.build_dir, &path, rss_xml.as_bytes()).map_err(|e| Error::io(&path, e))
+}
+
+/// Feed of a single section, `posts` are all posts of the site.
+pub fn render_section_feed(site: &Site, section: &Section, posts: &[Post]) -> String {
+    let section_posts: Vec<Post> = posts.iter().filter(|p| p.section == section.name).cloned().collect();
+    let name = format!("{} - {}", site.config.name, section.name);
+    rss::generate_rss(&section_posts, site.authors(), &name, &site.config.url)
+}
+
+pub fn write_section_feed(site: &Site, cache: &BuildCache, section: &Section, posts: &[Post]) -> Result<()> {
+    let path = section_feed_path(&section.name);
+    let rss_xml = render_section_feed(site, section, posts);
+    cache.write(&site.config.build_dir, &path, rss_xml.as_bytes()).map_err(|e| Error::io(&path, e))
+}
+
+pub fn write_sitemap(config: &SiteConfig, cache: &BuildCache, posts: &[Post], pages: &[StaticPage]) -> Result<()> {
+    let sitemap = sitemap::generate_sitemap(posts, pages, &config.url);
+    cache
+        .write(&config.build_dir, Path::new(SITEMAP_PATH), sitemap.as_bytes())
+        .map_err(|e| Error::io(Path::new(SITEMAP_PATH), e))
+}
+
+/// Render every page of the loaded posts without writing anything, returns the number of pages.
+pub fn check_site(site: &Site) -> Result<usize> {
+    let (pages, rendered) = render_posts(site, &BuildCache::disabled(), site.posts())?;
+    let posts = &with_word_counts(site.posts(), &rendered);
+    let indices = render_indices(site, posts)?;
+    let (static_pages, _) = render_static_pages(site)?;
+    render_feed(site, posts);
+    Ok(pages.len() + indices.len() + static_pages.len())
+}
 
-    let path = PathBuf::from(format!("{}index.html", path));
-    std::fs::write(path, html).expect(&format!("{template_name} html write"));
+/// Rendered markdown of a build, in the same order as `Site::posts` and `Site::pages`.
+pub struct RenderedSite {
+    pub posts: Vec<Rendered>,
+    pub pages: Vec<Rendered>,
 }
 
-pub fn copy_assets(source: &str, dest: &str) {
-    let folder = fs::read_dir(source).unwrap();
+/// Render and write every page, feed, asset and image of the loaded posts.
+/// Returns the rendered markdown of each post, in the same order as the posts.
+pub fn build_all(site: &Site, cache: &BuildCache) -> Result<RenderedSite> {
+    let config = &site.config;
+    let (mut pages, rendered) = render_posts(site, cache, site.posts())?;
+    let posts = &with_word_counts(site.posts(), &rendered);
+    pages.extend(render_indices(site, posts)?);
+    pages.extend(posts.iter().flat_map(render_aliases));
+    let (static_pages, rendered_pages) = render_static_pages(site)?;
+    pages.extend(static_pages);
+    write_pages(config, cache, &pages)?;
+    write_redirects(config, cache, posts)?;
+
+    // rss
+    write_feed(site, cache, posts).map_err(|e| Error::io(Path::new(FEED_PATH), e))?;
+    for section in &config.sections {
+        write_section_feed(site, cache, section, posts)?;
+    }
+    for taxonomy in config.taxonomies.iter().filter(|t| t.feeds) {
+        for (term, term_posts) in taxonomy_terms(posts, &taxonomy.name) {
+            write_term_feed(site, cache, &taxonomy.name, term, &term_posts)?;
+        }
+    }
+    write_sitemap(config, cache, posts, site.pages())?;
+    // assets
+    copy_assets(config, cache, &config.assets_dir, Path::new(""))?;
+    let mut scales = image_scales(&rendered);
+    scales.extend(image_scales(&rendered_pages));
+    process_images(config, cache, posts, &scales)?;
+    Ok(RenderedSite { posts: rendered, pages: rendered_pages })
+}
+
+/// Load the posts and build the site, only files whose inputs changed since the last
+/// build are rebuilt. Returns the rendered markdown of each post, see `build_all`.
+pub fn build_site(site: &mut Site) -> Result<RenderedSite> {
+    init_build(&site.config)?;
+    site.load_posts()?;
+    site.load_pages()?;
+    let cache = BuildCache::load(&site.config);
+    let rendered = build_all(site, &cache)?;
+    site.set_posts(with_word_counts(site.posts(), &rendered.posts));
+    finish(&site.config, cache)?;
+    Ok(rendered)
+}
+
+/// Remove stale outputs and save the build cache.
+pub fn finish(config: &SiteConfig, cache: BuildCache) -> Result<()> {
+    let removed = cache.finish(&config.build_dir).map_err(|e| Error::io(Path::new(&config.build_dir), e))?;
+    if removed > 0 {
+        log::info!("Removed {} stale files", removed);
+    }
+    Ok(())
+}
+
+/// Copy a single asset to `dest`, relative to the build folder.
+pub fn copy_asset(config: &SiteConfig, cache: &BuildCache, file_path: &Path, dest: &Path) -> Result<()> {
+    let contents = if file_path.extension().unwrap_or_default() == "css" && config.minify {
+        // minify css
+        let css = fs::read_to_string(file_path).map_err(|e| Error::io(file_path, e))?;
+        minify_css(&css).map_err(|message| Error::source(file_path, &css, None, message))?.into_bytes()
+    } else {
+        fs::read(file_path).map_err(|e| Error::io(file_path, e))?
+    };
+    cache.write(&config.build_dir, dest, &contents).map_err(|e| Error::io(dest, e))
+}
+
+/// Copy the assets in `source` to `dest`, relative to the build folder.
+pub fn copy_assets(config: &SiteConfig, cache: &BuildCache, source: &str, dest: &Path) -> Result<()> {
+    let folder = fs::read_dir(source).map_err(|e| Error::io(Path::new(source), e))?;
     for file in folder {
-        let file_path = file.unwrap().path();
-        let file_name = file_path.file_name().unwrap().to_str().unwrap();
+        let file_path = file?.path();
+        let file_name = file_path.file_name().unwrap_or_default().to_string_lossy().to_string();
         if file_name.starts_with('.') {
             continue;
         } else if file_path.is_dir() {
-            copy_assets(
-                PathBuf::from(source).join(file_name).to_str().unwrap(),
-                PathBuf::from(dest).join(file_name).to_str().unwrap(),
-            );
+            copy_assets(config, cache, &file_path.to_string_lossy(), &dest.join(&file_name))?;
             continue;
         };
 
-        let path = PathBuf::from(dest).join(file_name);
+        copy_asset(config, cache, &file_path, &dest.join(file_name))?;
+    }
+    Ok(())
+}
+
+/// Output of an image in `SiteConfig::images_dir`.
+pub fn image_dest(config: &SiteConfig, file_path: &Path) -> PathBuf {
+    let relative = file_path.strip_prefix(&config.images_dir).unwrap_or(file_path);
+    Path::new(IMAGES_PATH.trim_start_matches('/')).join(image_convert::converted_name(&relative.to_string_lossy()))
+}
+
+/// Output of a file of the page bundle of `post`, next to the post page.
+pub fn bundle_file_dest(post: &Post, file_path: &Path) -> Option<PathBuf> {
+    let relative = file_path.strip_prefix(post.bundle_dir()?).ok()?;
+    let name = image_convert::converted_name(&relative.to_string_lossy());
+    Some(Path::new(post.folder_url().trim_start_matches('/')).join(name))
+}
 
-        if file_path.extension().unwrap() == "css" && MINIFY {
-            // minify css
-            let mut css = fs::read_to_string(file_path).unwrap();
-            css = minify_css(&css);
-            fs::write(path, css).expect("css write");
+fn visit_files(config: &SiteConfig, ignore: &GlobSet, dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for path in sorted_entries(dir)? {
+        if config.is_ignored(ignore, &path) {
+            continue;
+        }
+        if path.is_dir() {
+            visit_files(config, ignore, &path, files)?;
         } else {
-            fs::copy(file_path, path).expect("copy file");
+            files.push(path);
         }
     }
+    Ok(())
 }
 
-fn process_single_image(file: &DirEntry, dest: &str, scaling: &f64) -> bool {
-    let file_path = file.path();
-    let file_name = file_path.file_name().unwrap().to_str().unwrap().to_string();
-    if file_name.starts_with('.') || file_path.is_dir() {
-        return false;
-    }
+/// Images, documents and other files of the page bundle of `post`, empty for posts that are single files.
+pub fn bundle_files(config: &SiteConfig, post: &Post) -> Result<Vec<PathBuf>> {
+    let Some(dir) = post.bundle_dir() else {
+        return Ok(Vec::new());
+    };
+    let mut files = Vec::new();
+    visit_files(config, &config.ignore_globs(), dir, &mut files).map_err(|e| Error::io(dir, e))?;
+    files.retain(|f| *f != post.path);
+    Ok(files)
+}
 
-    let file_name = modify_url(file_name);
-    let dest = PathBuf::from(format!("{}{}{}", BUILD_DIR, dest, file_name));
+/// Convert a single image, or copy another file, to `dest` unless the cache has it already.
+pub fn process_image(config: &SiteConfig, cache: &BuildCache, file_path: &Path, dest: &Path, scaling: f64) -> Result<()> {
+    let hash = cache::hash(&(cache::hash_file(file_path), scaling.to_bits()));
+    if !cache.image_is_fresh(&config.build_dir, dest, hash) {
+        let dest_path = Path::new(&config.build_dir).join(dest);
+        if let Some(parent) = dest_path.parent() {
+            fs::create_dir_all(parent).map_err(|e| Error::io(parent, e))?;
+        }
+        image_convert::convert_image(file_path.to_path_buf(), dest_path, scaling)?;
+    }
+    cache.insert_image(dest, hash);
+    Ok(())
+}
 
-    image_convert::convert_image(file_path, dest, *scaling);
-    true
+/// Scale requested for an image, 1.0 if no post asked for one.
+pub fn image_scale(file_path: &Path, image_scales: &ImageScales) -> f64 {
+    *image_scales.get(file_path).unwrap_or(&1.0)
 }
 
-pub fn process_images(source: &str, dest: &str, image_scales: &HashMap<String, f64>) {
-    let files: Vec<DirEntry> = fs::read_dir(source)
-        .unwrap()
-        .map(|result| result.unwrap())
+/// Convert every image under `SiteConfig::images_dir` and every file of the page bundles,
+/// the errors of all failed files are reported together.
+pub fn process_images(config: &SiteConfig, cache: &BuildCache, posts: &[Post], image_scales: &ImageScales) -> Result<()> {
+    let source = Path::new(&config.images_dir);
+    let mut images = Vec::new();
+    visit_files(config, &config.ignore_globs(), source, &mut images).map_err(|e| Error::io(source, e))?;
+    let mut files: Vec<(PathBuf, PathBuf)> = images
+        .into_iter()
+        .map(|f| {
+            let dest = image_dest(config, &f);
+            (f, dest)
+        })
         .collect();
+    for post in posts {
+        for file in bundle_files(config, post)? {
+            if let Some(dest) = bundle_file_dest(post, &file) {
+                files.push((file, dest));
+            }
+        }
+    }
 
-    let _result: Vec<bool> = files
+    let errors: Vec<Error> = files
         .par_iter()
-        .map(|f| {
-            process_single_image(
-                f,
-                dest,
-                image_scales
-                    .get(&f.path().file_name().unwrap().to_str().unwrap().to_string())
-                    .unwrap_or(&1.0),
-            )
-        })
+        .filter_map(|(f, dest)| process_image(config, cache, f, dest, image_scale(f, image_scales)).err())
         .collect();
+    Error::collect(errors)
 }
@@ -0,0 +1,187 @@
+use crate::config::SiteConfig;
+use crate::markdown::Rendered;
+use crate::post::PostDate;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Rendered markdown of a post along with the hashes it was rendered from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedPost {
+    /// Hash of the markdown and the config
+    pub hash: u64,
+    /// Hash of the markdown alone
+    #[serde(default)]
+    pub contents: u64,
+    /// `updated` of the post when it was rendered
+    #[serde(default)]
+    pub updated: Option<PostDate>,
+    /// Hashes of the embedded image files, their sizes end up in the html
+    pub images: BTreeMap<PathBuf, u64>,
+    pub rendered: Rendered,
+}
+
+/// Version of the manifest format, manifests of other versions are not used.
+const MANIFEST_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    version: u32,
+    /// Post permalink -> rendered markdown, slugs are only unique within a section
+    posts: BTreeMap<String, CachedPost>,
+    /// Image output path -> hash of the source image and its scale
+    images: BTreeMap<PathBuf, u64>,
+    /// Every file written to the build folder -> hash of its contents
+    outputs: BTreeMap<PathBuf, u64>,
+}
+
+/// Build cache persisted between runs in `SiteConfig::cache_file`.
+///
+/// Rendering markdown (KaTeX, syntect) and encoding images are skipped when their inputs
+/// are unchanged, outputs are only written when their contents change and outputs of the
+/// previous build that were not produced again are deleted.
+/// Hashes use the std hasher, a toolchain update at worst causes one full rebuild.
+pub struct BuildCache {
+    path: Option<PathBuf>,
+    previous: Manifest,
+    current: Mutex<Manifest>,
+}
+
+pub fn hash<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Hash of a file's contents, 0 if it cannot be read.
+pub fn hash_file(path: &Path) -> u64 {
+    fs::read(path).map(|bytes| hash(&bytes)).unwrap_or(0)
+}
+
+/// Hash of the config, any setting change invalidates every cached post.
+pub fn hash_config(config: &SiteConfig) -> u64 {
+    hash(&toml::to_string(config).expect("serialisable config"))
+}
+
+impl BuildCache {
+    /// Cache that starts empty and is never saved.
+    pub fn disabled() -> BuildCache {
+        BuildCache {
+            path: None,
+            previous: Manifest::default(),
+            current: Mutex::new(Manifest::default()),
+        }
+    }
+
+    /// Load the previous manifest, a missing or unreadable manifest gives a full rebuild.
+    pub fn load(config: &SiteConfig) -> BuildCache {
+        let path = PathBuf::from(&config.cache_file);
+        let previous = fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| serde_yaml::from_str::<Manifest>(&contents).ok())
+            .filter(|manifest| manifest.version == MANIFEST_VERSION)
+            .unwrap_or_default();
+        BuildCache {
+            path: Some(path),
+            previous,
+            current: Mutex::new(Manifest::default()),
+        }
+    }
+
+    /// Like `load`, but outputs of the previous build are kept unless they are `forget`-ed.
+    /// Used for partial rebuilds that only produce what changed.
+    pub fn resume(config: &SiteConfig) -> BuildCache {
+        let cache = BuildCache::load(config);
+        *cache.current.lock().expect("cache lock") = cache.previous.clone();
+        cache
+    }
+
+    /// Drop an output so `finish` deletes it.
+    pub fn forget(&self, dest: &Path) {
+        let mut current = self.current.lock().expect("cache lock");
+        current.outputs.remove(dest);
+        current.images.remove(dest);
+    }
+
+    /// Previously rendered markdown of the post at `permalink` if nothing it depends on changed.
+    pub fn post(&self, permalink: &str, hash: u64) -> Option<CachedPost> {
+        let cached = self.previous.posts.get(permalink)?;
+        let images_unchanged = cached.images.iter().all(|(path, h)| hash_file(path) == *h);
+        if cached.hash == hash && images_unchanged {
+            Some(cached.clone())
+        } else {
+            None
+        }
+    }
+
+    /// The post at `permalink` as rendered by the previous build.
+    pub fn previous_post(&self, permalink: &str) -> Option<&CachedPost> {
+        self.previous.posts.get(permalink)
+    }
+
+    pub fn insert_post(&self, permalink: &str, post: CachedPost) {
+        let mut current = self.current.lock().expect("cache lock");
+        current.posts.insert(permalink.to_string(), post);
+    }
+
+    /// Whether the image at `dest` was already produced from a source with this hash.
+    pub fn image_is_fresh(&self, build_dir: &str, dest: &Path, hash: u64) -> bool {
+        self.previous.images.get(dest) == Some(&hash) && Path::new(build_dir).join(dest).exists()
+    }
+
+    pub fn insert_image(&self, dest: &Path, hash: u64) {
+        let mut current = self.current.lock().expect("cache lock");
+        current.images.insert(dest.to_path_buf(), hash);
+        current.outputs.insert(dest.to_path_buf(), hash);
+    }
+
+    /// Write `contents` to `dest` (relative to the build folder) unless it is unchanged.
+    pub fn write(&self, build_dir: &str, dest: &Path, contents: &[u8]) -> io::Result<()> {
+        let contents_hash = hash(contents);
+        let path = Path::new(build_dir).join(dest);
+        if self.previous.outputs.get(dest) != Some(&contents_hash) || !path.exists() {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&path, contents)?;
+        }
+        let mut current = self.current.lock().expect("cache lock");
+        current.outputs.insert(dest.to_path_buf(), contents_hash);
+        Ok(())
+    }
+
+    /// Delete outputs of the previous build that were not produced this time and save
+    /// the manifest. Returns the number of deleted files.
+    pub fn finish(self, build_dir: &str) -> io::Result<usize> {
+        let mut current = self.current.into_inner().expect("cache lock");
+        current.version = MANIFEST_VERSION;
+        let mut removed = 0;
+        for stale in self.previous.outputs.keys().filter(|p| !current.outputs.contains_key(*p)) {
+            let path = Path::new(build_dir).join(stale);
+            match fs::remove_file(&path) {
+                Ok(()) => removed += 1,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+                Err(err) => return Err(err),
+            }
+            // drop folders left empty, e.g. the folder of a deleted post
+            let mut folder = path.parent();
+            while let Some(f) = folder {
+                if f == Path::new(build_dir) || fs::remove_dir(f).is_err() {
+                    break;
+                }
+                folder = f.parent();
+            }
+        }
+        if let Some(path) = self.path {
+            let manifest = serde_yaml::to_string(&current).map_err(io::Error::other)?;
+            fs::write(path, manifest)?;
+        }
+        Ok(removed)
+    }
+}
@@ -0,0 +1,275 @@
+use crate::error::{Error, Position, Result};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+pub const CONFIG_FILE: &str = "blog.toml";
+
+/// Site wide settings, read from `blog.toml` in the project root.
+/// Every key is optional, missing keys fall back to `SiteConfig::default()`.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SiteConfig {
+    pub name: String,
+    pub url: String,
+    pub build_dir: String,
+    pub minify: bool,
+    pub clean_build: bool,
+    /// Build posts marked `draft: true`, they are left out otherwise
+    pub drafts: bool,
+    /// Build posts dated in the future, they are left out otherwise
+    pub future: bool,
+    /// Take `updated` from the last git commit of a post when its front matter has none
+    pub updated_from_git: bool,
+    /// Build cache manifest, see `cache::BuildCache`
+    pub cache_file: String,
+    /// Url of each post, `:year`, `:month`, `:day` and `:slug` are replaced.
+    /// Ends with `/` for `index.html` folders or `.html` for flat files
+    pub permalink: String,
+    pub posts_dir: String,
+    /// Globs of files and folders under the section folders that are not part of the site, matched
+    /// against paths relative to the section folder and against file names. Hidden files are always skipped
+    pub ignore: Vec<String>,
+    /// Standalone markdown pages, served at `/{slug}/` and left out of the post listings
+    pub pages_dir: String,
+    /// Author data, see `authors::Author`
+    pub authors_file: String,
+    pub images_dir: String,
+    pub templates_dir: String,
+    pub assets_dir: String,
+    /// Template of posts that do not set `template:`
+    pub post_template: String,
+    /// Tag -> template of posts with the tag that do not set `template:`
+    pub tag_templates: BTreeMap<String, String>,
+    /// Streams of posts besides the posts folder, `[[sections]]` tables
+    pub sections: Vec<Section>,
+    /// Groupings of posts besides tags, `[[taxonomies]]` tables
+    pub taxonomies: Vec<Taxonomy>,
+}
+
+/// A grouping of posts like tags, e.g. categories, read from a front matter key.
+/// Every term gets a page at `/{name}/{term}/` and the list of terms is at `/{name}/`.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Taxonomy {
+    pub name: String,
+    /// Front matter key with a term or a list of terms, `name` if not given
+    #[serde(default)]
+    pub key: String,
+    /// Write a feed for every term at `/{name}/{term}/rss.xml`
+    #[serde(default)]
+    pub feeds: bool,
+}
+
+/// Name of the section of the posts folder, configured by the top level keys.
+pub const MAIN_SECTION: &str = "posts";
+
+/// Url prefixes of built in pages, sections, taxonomies and pages cannot use them as names.
+/// A page with the slug `about` replaces the built in about page.
+pub const RESERVED_NAMES: [&str; 5] = ["tags", "authors", "series", "images", "about"];
+
+/// Order of the posts on the index page of a section.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Newest,
+    Oldest,
+    Title,
+}
+
+/// A stream of posts, e.g. notes or talks, with its own folder, urls, index page and feed.
+/// The index is served at `/{name}/` and the feed at `/{name}/rss.xml`.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Section {
+    pub name: String,
+    /// Folder of the posts, outside of the other sections' folders
+    pub dir: String,
+    /// Url of each post like `SiteConfig::permalink`, `/{name}/:slug/` if not given
+    #[serde(default)]
+    pub permalink: String,
+    /// Template of posts that do not set `template:` and have no tag template, `SiteConfig::post_template` if not given
+    #[serde(default)]
+    pub template: Option<String>,
+    #[serde(default)]
+    pub sort: SortOrder,
+    /// List the posts on the home page
+    #[serde(default)]
+    pub home: bool,
+    /// Include the posts in the main feed
+    #[serde(default)]
+    pub feed: bool,
+}
+
+impl Default for SiteConfig {
+    fn default() -> Self {
+        SiteConfig {
+            name: "blog".to_string(),
+            url: "https://grobins.xyz".to_string(),
+            build_dir: "build".to_string(),
+            minify: true,
+            clean_build: false,
+            drafts: false,
+            future: false,
+            updated_from_git: false,
+            cache_file: ".build-cache.yml".to_string(),
+            permalink: "/posts/:slug/".to_string(),
+            posts_dir: "posts".to_string(),
+            ignore: ["*~", "*.swp", "*.bak", "#*#"].map(String::from).to_vec(),
+            pages_dir: "pages".to_string(),
+            authors_file: "authors.toml".to_string(),
+            images_dir: "posts/images".to_string(),
+            templates_dir: "templates".to_string(),
+            assets_dir: "assets".to_string(),
+            post_template: "post.html".to_string(),
+            tag_templates: BTreeMap::new(),
+            sections: Vec::new(),
+            taxonomies: Vec::new(),
+        }
+    }
+}
+
+impl SiteConfig {
+    pub fn parse(contents: &str) -> Result<SiteConfig, toml::de::Error> {
+        let mut config: SiteConfig = toml::from_str(contents)?;
+        // urls are joined as `{url}/posts/...`
+        config.url = config.url.trim_end_matches('/').to_string();
+        for section in &mut config.sections {
+            if section.permalink.is_empty() {
+                section.permalink = format!("/{}/:slug/", section.name);
+            }
+        }
+        for section in config.all_sections() {
+            let permalink = &section.permalink;
+            if !permalink.starts_with('/') || !(permalink.ends_with('/') || permalink.ends_with(".html")) {
+                return Err(serde::de::Error::custom(format!(
+                    "permalink {permalink:?} must start with / and end with / or .html"
+                )));
+            }
+        }
+        for taxonomy in &mut config.taxonomies {
+            if taxonomy.key.is_empty() {
+                taxonomy.key = taxonomy.name.clone();
+            }
+        }
+        config.check_sections().map_err(serde::de::Error::custom)?;
+        config.check_taxonomies().map_err(serde::de::Error::custom)?;
+        for pattern in &config.ignore {
+            Glob::new(pattern).map_err(|e| serde::de::Error::custom(format!("invalid ignore glob: {e}")))?;
+        }
+        Ok(config)
+    }
+
+    /// Section names are url segments, unique and not reserved, section folders do not overlap.
+    fn check_sections(&self) -> Result<(), String> {
+        let sections = self.all_sections();
+        for (i, section) in sections.iter().enumerate() {
+            let name = &section.name;
+            if name.is_empty() || name.contains(['/', '\\']) {
+                return Err(format!("section name {name:?} must be a non-empty name without /"));
+            }
+            if RESERVED_NAMES.contains(&name.as_str()) {
+                return Err(format!("section name {name:?} is reserved, /{name}/ is used by the blog"));
+            }
+            for other in &sections[..i] {
+                if other.name == *name {
+                    return Err(format!("section {name:?} is defined twice"));
+                }
+                let (dir, other_dir) = (Path::new(&section.dir), Path::new(&other.dir));
+                if dir.starts_with(other_dir) || other_dir.starts_with(dir) {
+                    return Err(format!("folders of sections {:?} and {name:?} overlap", other.name));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Taxonomy names are url segments, unique and not reserved or used by sections.
+    fn check_taxonomies(&self) -> Result<(), String> {
+        for (i, taxonomy) in self.taxonomies.iter().enumerate() {
+            let name = &taxonomy.name;
+            if name.is_empty() || name.contains(['/', '\\']) {
+                return Err(format!("taxonomy name {name:?} must be a non-empty name without /"));
+            }
+            if taxonomy.key == "tags" {
+                return Err("tags are built in, they cannot be a taxonomy".to_string());
+            }
+            if RESERVED_NAMES.contains(&name.as_str()) {
+                return Err(format!("taxonomy name {name:?} is reserved, /{name}/ is used by the blog"));
+            }
+            if self.taxonomies[..i].iter().any(|other| other.name == *name) {
+                return Err(format!("taxonomy {name:?} is defined twice"));
+            }
+            if self.all_sections().iter().any(|section| section.name == *name) {
+                return Err(format!("taxonomy {name:?} has the name of a section, both would use the url /{name}/"));
+            }
+        }
+        Ok(())
+    }
+
+    /// The section of the posts folder, from the top level `posts_dir`, `permalink` and `post_template`.
+    /// It is listed on the home page and in the main feed.
+    pub fn main_section(&self) -> Section {
+        Section {
+            name: MAIN_SECTION.to_string(),
+            dir: self.posts_dir.clone(),
+            permalink: self.permalink.clone(),
+            template: None,
+            sort: SortOrder::Newest,
+            home: true,
+            feed: true,
+        }
+    }
+
+    /// The main section followed by the `[[sections]]`.
+    pub fn all_sections(&self) -> Vec<Section> {
+        let mut sections = vec![self.main_section()];
+        sections.extend(self.sections.iter().cloned());
+        sections
+    }
+
+    /// Section a post file belongs to, the main section for files outside of every section folder.
+    pub fn section_of(&self, path: &Path) -> Section {
+        self.sections
+            .iter()
+            .find(|section| path.starts_with(&section.dir))
+            .cloned()
+            .unwrap_or_else(|| self.main_section())
+    }
+
+    /// Section by name, `None` for unknown names.
+    pub fn section(&self, name: &str) -> Option<Section> {
+        self.all_sections().into_iter().find(|section| section.name == name)
+    }
+
+    /// The `ignore` globs, invalid ones are left out, `parse` reports those.
+    pub fn ignore_globs(&self) -> GlobSet {
+        let mut builder = GlobSetBuilder::new();
+        for glob in self.ignore.iter().filter_map(|pattern| Glob::new(pattern).ok()) {
+            builder.add(glob);
+        }
+        builder.build().unwrap_or_else(|_| GlobSet::empty())
+    }
+
+    /// Whether `path` is left out of the build: hidden or matching an `ignore` glob.
+    pub fn is_ignored(&self, globs: &GlobSet, path: &Path) -> bool {
+        let relative = path.strip_prefix(&self.section_of(path).dir).unwrap_or(path);
+        let name = path.file_name().unwrap_or_default();
+        name.to_string_lossy().starts_with('.') || globs.is_match(relative) || globs.is_match(name)
+    }
+
+    /// Load the config file, a missing file gives the default config.
+    pub fn load(path: &Path) -> Result<SiteConfig> {
+        if !path.exists() {
+            return Ok(SiteConfig::default());
+        }
+        let contents = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+        SiteConfig::parse(&contents).map_err(|e| {
+            let position = e.span().map(|span| Position::from_offset(&contents, span.start));
+            Error::source(path, &contents, position, e.message())
+        })
+    }
+}
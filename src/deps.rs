@@ -0,0 +1,325 @@
+use crate::build::{
+    self, ImageScales, RenderedSite, ABOUT_TEMPLATE, ALL_TAGS_TEMPLATE, ALL_TERMS_TEMPLATE, AUTHOR_TEMPLATE,
+    INDEX_TEMPLATE, NOT_FOUND_TEMPLATE,
+};
+use crate::config::{SiteConfig, MAIN_SECTION};
+use crate::markdown::Rendered;
+use crate::page::StaticPage;
+use crate::post::Post;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use tera::Tera;
+
+/// Something the build writes that can be rebuilt on its own.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Target {
+    /// Post page, by source file
+    Post(PathBuf),
+    /// Standalone page, by source file
+    Page(PathBuf),
+    Index,
+    About,
+    NotFound,
+    Tag(String),
+    AllTags,
+    /// Author page, by author id
+    Author(String),
+    /// Series overview page, by series name
+    Series(String),
+    /// Index page of a `[[sections]]` section, by name
+    Section(String),
+    /// Term page, by taxonomy name and term
+    Term(String, String),
+    /// List of the terms of a taxonomy, by name
+    AllTerms(String),
+    Feed,
+    /// Feed of a `[[sections]]` section, by name
+    SectionFeed(String),
+    /// Feed of a term of a taxonomy with `feeds`, by taxonomy name and term
+    TermFeed(String, String),
+    Sitemap,
+    /// `_redirects` file
+    Redirects,
+    /// Converted image or file of a page bundle, by source file
+    Image(PathBuf),
+    /// Copied asset, by source file
+    Asset(PathBuf),
+}
+
+impl Target {
+    /// Template of the page, posts and pages choose theirs, see `DependencyGraph::template`.
+    pub fn template(&self) -> Option<&'static str> {
+        match self {
+            Target::Post(_) | Target::Page(_) => None,
+            Target::Index | Target::Tag(_) | Target::Series(_) | Target::Section(_) | Target::Term(..) => {
+                Some(INDEX_TEMPLATE)
+            }
+            Target::AllTerms(_) => Some(ALL_TERMS_TEMPLATE),
+            Target::About => Some(ABOUT_TEMPLATE),
+            Target::NotFound => Some(NOT_FOUND_TEMPLATE),
+            Target::AllTags => Some(ALL_TAGS_TEMPLATE),
+            Target::Author(_) => Some(AUTHOR_TEMPLATE),
+            Target::Feed
+            | Target::SectionFeed(_)
+            | Target::TermFeed(..)
+            | Target::Sitemap
+            | Target::Redirects
+            | Target::Image(_)
+            | Target::Asset(_) => None,
+        }
+    }
+}
+
+/// Which targets depend on which source files, kept up to date between rebuilds in watch mode.
+#[derive(Default)]
+pub struct DependencyGraph {
+    /// Post source -> the post as last loaded
+    posts: BTreeMap<PathBuf, Post>,
+    /// Page source -> the page as last loaded
+    static_pages: BTreeMap<PathBuf, StaticPage>,
+    /// Post or page source -> image files it embeds
+    images: BTreeMap<PathBuf, Vec<PathBuf>>,
+    /// Post or page source -> image scales it requests
+    scales: BTreeMap<PathBuf, ImageScales>,
+    /// Post source -> files of its page bundle
+    bundles: BTreeMap<PathBuf, Vec<PathBuf>>,
+}
+
+impl DependencyGraph {
+    pub fn new(config: &SiteConfig, posts: Vec<Post>, pages: Vec<StaticPage>, rendered: RenderedSite) -> DependencyGraph {
+        let mut graph = DependencyGraph::default();
+        for (post, rendered) in posts.into_iter().zip(rendered.posts) {
+            graph.set_post(config, post, &rendered);
+        }
+        for (page, rendered) in pages.into_iter().zip(rendered.pages) {
+            let path = page.path.clone();
+            graph.insert_page(page);
+            graph.set_rendered(config, &path, &rendered);
+        }
+        graph
+    }
+
+    pub fn posts(&self) -> Vec<Post> {
+        self.posts.values().cloned().collect()
+    }
+
+    pub fn post(&self, path: &Path) -> Option<&Post> {
+        self.posts.get(path)
+    }
+
+    pub fn page(&self, path: &Path) -> Option<&StaticPage> {
+        self.static_pages.get(path)
+    }
+
+    /// Image files embedded by a post or page.
+    pub fn post_images(&self, path: &Path) -> &[PathBuf] {
+        self.images.get(path).map(|i| i.as_slice()).unwrap_or_default()
+    }
+
+    /// Files of the page bundle of a post, as of its last render.
+    pub fn bundle_files(&self, path: &Path) -> &[PathBuf] {
+        self.bundles.get(path).map(|f| f.as_slice()).unwrap_or_default()
+    }
+
+    /// Post whose page bundle contains `file`.
+    pub fn bundle_post(&self, file: &Path) -> Option<&Post> {
+        self.posts
+            .values()
+            .find(|post| post.path != file && post.bundle_dir().is_some_and(|dir| file.starts_with(dir)))
+    }
+
+    /// Output of an image or bundle file.
+    pub fn image_dest(&self, config: &SiteConfig, file: &Path) -> PathBuf {
+        self.bundle_post(file)
+            .and_then(|post| build::bundle_file_dest(post, file))
+            .unwrap_or_else(|| build::image_dest(config, file))
+    }
+
+    /// Image scales requested by all posts and pages.
+    pub fn image_scales(&self) -> ImageScales {
+        self.scales.values().flat_map(|s| s.clone()).collect()
+    }
+
+    pub fn set_post(&mut self, config: &SiteConfig, post: Post, rendered: &Rendered) {
+        let path = post.path.clone();
+        self.insert_post(post);
+        self.set_rendered(config, &path, rendered);
+    }
+
+    /// Add or replace a post, its images are known once it is rendered, see `set_rendered`.
+    pub fn insert_post(&mut self, post: Post) {
+        self.posts.insert(post.path.clone(), post);
+    }
+
+    pub fn set_rendered(&mut self, config: &SiteConfig, path: &Path, rendered: &Rendered) {
+        self.images.insert(path.to_path_buf(), rendered.images.clone());
+        self.scales.insert(path.to_path_buf(), rendered.image_scale.clone());
+        if let Some(post) = self.posts.get_mut(path) {
+            post.set_word_count(rendered);
+            // files added to the bundle since are picked up
+            let files = build::bundle_files(config, post).unwrap_or_default();
+            self.bundles.insert(path.to_path_buf(), files);
+        }
+    }
+
+    /// Add or replace a page, like `insert_post`.
+    pub fn insert_page(&mut self, page: StaticPage) {
+        self.static_pages.insert(page.path.clone(), page);
+    }
+
+    pub fn remove_page(&mut self, path: &Path) -> Option<StaticPage> {
+        self.images.remove(path);
+        self.scales.remove(path);
+        self.static_pages.remove(path)
+    }
+
+    pub fn remove_post(&mut self, path: &Path) -> Option<Post> {
+        self.images.remove(path);
+        self.bundles.remove(path);
+        self.scales.remove(path);
+        self.posts.remove(path)
+    }
+
+    /// Template a target is rendered with.
+    fn template<'a>(&'a self, config: &'a SiteConfig, target: &Target) -> Option<&'a str> {
+        match target {
+            Target::Post(path) => self.posts.get(path).map(|post| build::post_template(config, post)),
+            Target::Page(path) => self.static_pages.get(path).map(build::page_template),
+            _ => target.template(),
+        }
+    }
+
+    /// Every page rendered from a template.
+    pub fn pages(&self, config: &SiteConfig) -> Vec<Target> {
+        let mut targets: Vec<Target> = self.posts.keys().cloned().map(Target::Post).collect();
+        targets.extend(self.static_pages.keys().cloned().map(Target::Page));
+        targets.extend([Target::Index, Target::About, Target::NotFound, Target::AllTags]);
+        let posts = self.posts();
+        targets.extend(build::tags(&posts).into_keys().map(|t| Target::Tag(t.to_string())));
+        targets.extend(build::author_posts(&posts).into_keys().map(|a| Target::Author(a.to_string())));
+        targets.extend(build::series(&posts).into_keys().map(|s| Target::Series(s.to_string())));
+        targets.extend(config.sections.iter().map(|s| Target::Section(s.name.clone())));
+        for taxonomy in &config.taxonomies {
+            let terms = build::taxonomy_terms(&posts, &taxonomy.name).into_keys();
+            targets.extend(terms.map(|term| Target::Term(taxonomy.name.clone(), term.to_string())));
+            targets.push(Target::AllTerms(taxonomy.name.clone()));
+        }
+        targets
+    }
+
+    /// Feeds of the terms of the taxonomies with `feeds`.
+    fn term_feeds(&self, config: &SiteConfig) -> Vec<Target> {
+        let posts = self.posts();
+        let mut targets = Vec::new();
+        for taxonomy in config.taxonomies.iter().filter(|t| t.feeds) {
+            let terms = build::taxonomy_terms(&posts, &taxonomy.name).into_keys();
+            targets.extend(terms.map(|term| Target::TermFeed(taxonomy.name.clone(), term.to_string())));
+        }
+        targets
+    }
+
+    /// Pages besides its own that show the post at `path`: its tag, term, author and series pages,
+    /// the term feeds, the other parts of its series and the posts linking to it as previous or next post.
+    pub fn related(&self, path: &Path) -> BTreeSet<Target> {
+        let mut targets = BTreeSet::new();
+        let Some(post) = self.posts.get(path) else {
+            return targets;
+        };
+        targets.extend(post.metadata.tags.iter().cloned().map(Target::Tag));
+        for (name, terms) in &post.metadata.taxonomies {
+            targets.extend(terms.iter().map(|term| Target::Term(name.clone(), term.clone())));
+            targets.extend(terms.iter().map(|term| Target::TermFeed(name.clone(), term.clone())));
+        }
+        targets.extend(post.metadata.authors.iter().cloned().map(Target::Author));
+        if let Some(series) = &post.metadata.series {
+            targets.insert(Target::Series(series.clone()));
+            let parts = self.posts.values().filter(|p| p.metadata.series.as_ref() == Some(series));
+            targets.extend(parts.map(|p| Target::Post(p.path.clone())));
+        }
+        let (prev, next) = build::neighbours(self.posts.values().filter(|p| p.section == post.section), post);
+        targets.extend([prev, next].into_iter().flatten().map(|p| Target::Post(p.path.clone())));
+        for tag in &post.metadata.tags {
+            let tag_posts = self.posts.values().filter(|p| p.metadata.tags.contains(tag));
+            let (prev, next) = build::neighbours(tag_posts, post);
+            targets.extend([prev, next].into_iter().flatten().map(|p| Target::Post(p.path.clone())));
+        }
+        targets
+    }
+
+    /// Templates affected by a change to `name`: itself, templates extending it and templates
+    /// importing macros from it. Includes are not tracked so if no page uses any of these
+    /// templates directly the change is assumed to affect every template.
+    fn affected_templates(&self, config: &SiteConfig, tera: &Tera, name: &str) -> Option<BTreeSet<String>> {
+        let affected: BTreeSet<String> = tera
+            .templates
+            .values()
+            .filter(|t| {
+                t.name == name
+                    || t.parents.iter().any(|p| p == name)
+                    || t.imported_macro_files.iter().any(|(file, _)| file == name)
+            })
+            .map(|t| t.name.clone())
+            .collect();
+        let used = self.pages(config).iter().any(|t| self.template(config, t).is_some_and(|n| affected.contains(n)));
+        used.then_some(affected)
+    }
+
+    /// Targets to rebuild after `changed` was added, modified or removed.
+    /// Pages related to a post in its new version are only known once it is loaded again.
+    pub fn affected(&self, config: &SiteConfig, tera: &Tera, changed: &Path) -> BTreeSet<Target> {
+        let mut targets = BTreeSet::new();
+
+        if let Ok(name) = changed.strip_prefix(&config.templates_dir) {
+            let name = name.to_string_lossy().replace('\\', "/");
+            let templates = self.affected_templates(config, tera, &name);
+            targets.extend(self.pages(config).into_iter().filter(|t| match (&templates, self.template(config, t)) {
+                (Some(templates), Some(template)) => templates.contains(template),
+                (None, template) => template.is_some(),
+                _ => false,
+            }));
+        } else if build::is_ignored(config, changed) {
+            // scratch files and editor backups
+        } else if changed.starts_with(&config.images_dir) {
+            targets.insert(Target::Image(changed.to_path_buf()));
+            // image sizes are part of the post and page html
+            for (source, images) in &self.images {
+                if images.iter().any(|i| i == changed) {
+                    let page = self.static_pages.contains_key(source);
+                    targets.insert(if page { Target::Page(source.clone()) } else { Target::Post(source.clone()) });
+                }
+            }
+        } else if build::is_post_path(config, changed) {
+            targets.extend([
+                Target::Post(changed.to_path_buf()),
+                Target::Index,
+                Target::AllTags,
+                Target::Feed,
+                Target::Sitemap,
+                Target::Redirects,
+            ]);
+            targets.extend(self.related(changed));
+            let section = config.section_of(changed);
+            if section.name != MAIN_SECTION {
+                targets.extend([Target::Section(section.name.clone()), Target::SectionFeed(section.name)]);
+            }
+            // term counts
+            targets.extend(config.taxonomies.iter().map(|t| Target::AllTerms(t.name.clone())));
+        } else if build::is_page_path(config, changed) {
+            // a page may replace the about page, menu changes are only known once it is loaded
+            targets.extend([Target::Page(changed.to_path_buf()), Target::About, Target::Sitemap]);
+        } else if let Some(post) = self.bundle_post(changed) {
+            // image sizes and links to the bundle's files are part of the post html
+            targets.insert(Target::Image(changed.to_path_buf()));
+            targets.insert(Target::Post(post.path.clone()));
+        } else if changed == Path::new(&config.authors_file) {
+            // bylines, author pages, the about page and the feeds
+            targets.extend(self.pages(config));
+            targets.insert(Target::Feed);
+            targets.extend(config.sections.iter().map(|s| Target::SectionFeed(s.name.clone())));
+            targets.extend(self.term_feeds(config));
+        } else if changed.starts_with(&config.assets_dir) {
+            targets.insert(Target::Asset(changed.to_path_buf()));
+        }
+        targets
+    }
+}
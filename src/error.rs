@@ -0,0 +1,159 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Line and column in a source file, both start at 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    /// Position of the byte `offset` in `text`.
+    pub fn from_offset(text: &str, offset: usize) -> Position {
+        let before = &text[..text.floor_char_boundary(offset.min(text.len()))];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        Position {
+            line,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+
+    /// Move a position found in a part of a file that starts at `line` of the whole file.
+    pub fn offset_lines(self, line: usize) -> Position {
+        Position {
+            line: self.line + line - 1,
+            column: self.column,
+        }
+    }
+}
+
+/// The line `line` of `text`, used to show where an error is.
+pub fn snippet(text: &str, line: usize) -> Option<String> {
+    text.lines().nth(line.checked_sub(1)?).map(|l| l.trim_end().to_string())
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// Problem in a source file: invalid front matter, unbalanced `$` in a post, unknown config key...
+    Source {
+        path: PathBuf,
+        position: Option<Position>,
+        snippet: Option<String>,
+        message: String,
+    },
+    /// A template failed to parse or render
+    Template { name: String, message: String },
+    Io {
+        path: Option<PathBuf>,
+        source: io::Error,
+    },
+    Message(String),
+    /// Every error of a build, reported together
+    Multiple(Vec<Error>),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// Error at `position` of the source file `path` whose contents are `text`.
+    pub fn source(path: &Path, text: &str, position: Option<Position>, message: impl Into<String>) -> Error {
+        Error::Source {
+            path: path.to_path_buf(),
+            position,
+            snippet: position.and_then(|p| snippet(text, p.line)),
+            message: message.into(),
+        }
+    }
+
+    pub fn io(path: &Path, source: io::Error) -> Error {
+        Error::Io {
+            path: Some(path.to_path_buf()),
+            source,
+        }
+    }
+
+    /// Combine errors collected during a build, `Ok` if there are none.
+    pub fn collect(mut errors: Vec<Error>) -> Result<()> {
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.remove(0)),
+            _ => Err(Error::Multiple(errors)),
+        }
+    }
+
+    /// Number of individual errors.
+    pub fn count(&self) -> usize {
+        match self {
+            Error::Multiple(errors) => errors.iter().map(Error::count).sum(),
+            _ => 1,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Source {
+                path,
+                position,
+                snippet,
+                message,
+            } => {
+                write!(f, "{}", path.display())?;
+                if let Some(p) = position {
+                    write!(f, ":{}:{}", p.line, p.column)?;
+                }
+                write!(f, ": {}", message)?;
+                if let (Some(p), Some(snippet)) = (position, snippet) {
+                    let gutter = p.line.to_string().len();
+                    write!(f, "\n{:gutter$} |\n{} | {}", "", p.line, snippet)?;
+                    write!(f, "\n{:gutter$} | {:>column$}", "", "^", column = p.column)?;
+                }
+                Ok(())
+            }
+            Error::Template { name, message } => write!(f, "template {}: {}", name, message),
+            Error::Io { path: Some(path), source } => write!(f, "{}: {}", path.display(), source),
+            Error::Io { path: None, source } => write!(f, "{}", source),
+            Error::Message(message) => write!(f, "{}", message),
+            Error::Multiple(errors) => {
+                for (i, e) in errors.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    writeln!(f, "{}", e)?;
+                }
+                write!(f, "{} errors", self.count())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(source: io::Error) -> Error {
+        Error::Io { path: None, source }
+    }
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Error {
+        Error::Message(message)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(message: &str) -> Error {
+        Error::Message(message.to_string())
+    }
+}
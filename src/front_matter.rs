@@ -0,0 +1,259 @@
+//! Splitting posts into front matter and markdown.
+
+use crate::error::Position;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Syntax of the front matter, given by its delimiter lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Between `---` lines
+    Yaml,
+    /// Between `+++` lines
+    Toml,
+}
+
+impl Format {
+    pub fn delimiter(self) -> &'static str {
+        match self {
+            Format::Yaml => "---",
+            Format::Toml => "+++",
+        }
+    }
+
+    /// Format opened by `line`, trailing whitespace is allowed.
+    fn from_delimiter(line: &str) -> Option<Format> {
+        match line.trim_end() {
+            "---" => Some(Format::Yaml),
+            "+++" => Some(Format::Toml),
+            _ => None,
+        }
+    }
+}
+
+/// Front matter and markdown of a post. The front matter starts on the second line of the file.
+#[derive(Debug, Clone, Copy)]
+pub struct FrontMatter<'a> {
+    pub format: Format,
+    /// Text between the delimiter lines
+    pub text: &'a str,
+    pub body: &'a str,
+    /// Line of the file `body` starts on
+    pub body_line: usize,
+}
+
+/// `contents` with `\n` line endings and without a byte order mark.
+pub fn normalize(contents: &str) -> String {
+    contents.strip_prefix('\u{feff}').unwrap_or(contents).replace("\r\n", "\n")
+}
+
+/// Split normalized `contents`, `None` if the file does not start with a delimiter line.
+/// The front matter ends at the first line that is only the same delimiter, so indented
+/// or inline `---` in values are not mistaken for the end.
+pub fn split(contents: &str) -> Result<Option<FrontMatter<'_>>, (Position, String)> {
+    let mut lines = contents.split_inclusive('\n');
+    let Some((first, format)) = lines.next().and_then(|l| Some((l, Format::from_delimiter(l)?))) else {
+        return Ok(None);
+    };
+    let start = first.len();
+    let mut offset = start;
+    for (i, line) in lines.enumerate() {
+        if Format::from_delimiter(line) == Some(format) {
+            return Ok(Some(FrontMatter {
+                format,
+                text: &contents[start..offset],
+                body: &contents[offset + line.len()..],
+                // `i` counts from the second line
+                body_line: i + 3,
+            }));
+        }
+        offset += line.len();
+    }
+    let message = format!("front matter is not closed with a {} line", format.delimiter());
+    Err((Position { line: 1, column: 1 }, message))
+}
+
+/// `message` of serde_yaml without its ` at line 4 column 1` locations.
+fn strip_locations(message: &str) -> String {
+    const AT_LINE: &str = " at line ";
+    let is_digit = |c: char| c.is_ascii_digit();
+    let mut stripped = String::new();
+    let mut rest = message;
+    while let Some(i) = rest.find(AT_LINE) {
+        let after = &rest[i + AT_LINE.len()..];
+        let line_end = after.trim_start_matches(is_digit);
+        if line_end.len() == after.len() {
+            // not followed by a line number
+            stripped.push_str(&rest[..i + AT_LINE.len()]);
+            rest = after;
+            continue;
+        }
+        stripped.push_str(&rest[..i]);
+        rest = line_end.strip_prefix(" column ").map_or(line_end, |column| column.trim_start_matches(is_digit));
+    }
+    stripped.push_str(rest);
+    stripped
+}
+
+impl FrontMatter<'_> {
+    /// Deserialize the front matter, error positions are in the whole file.
+    pub fn parse<T: DeserializeOwned>(&self) -> Result<T, (Option<Position>, String)> {
+        match self.format {
+            Format::Yaml => serde_yaml::from_str(self.text).map_err(|e| {
+                let position = e.location().map(|l| Position { line: l.line() + 1, column: l.column() });
+                // the locations are relative to the front matter, the position is reported separately
+                let message = match position {
+                    Some(_) => strip_locations(&e.to_string()),
+                    None => e.to_string(),
+                };
+                (position, message)
+            }),
+            Format::Toml => toml::from_str(self.text).map_err(|e| {
+                let position = e.span().map(|span| Position::from_offset(self.text, span.start).offset_lines(2));
+                (position, e.message().to_string())
+            }),
+        }
+    }
+
+    /// Keys of the front matter that are not fields of `known`, the front matter parsed into it.
+    /// Parsed separately, flattening would lose the positions of errors in known fields.
+    pub fn extra<T: Serialize>(&self, known: &T) -> Result<BTreeMap<String, tera::Value>, (Option<Position>, String)> {
+        let mut extra: BTreeMap<String, tera::Value> = self.parse()?;
+        if let Ok(tera::Value::Object(known)) = tera::to_value(known) {
+            extra.retain(|key, _| !known.contains_key(key));
+        }
+        Ok(extra)
+    }
+
+    /// Line of the file setting `key`, for values that parse but are not valid for the blog.
+    pub fn key_line(&self, key: &str) -> Option<usize> {
+        self.text
+            .lines()
+            .position(|line| {
+                line.strip_prefix(key)
+                    .is_some_and(|rest| matches!(rest.trim_start().chars().next(), Some(':' | '=')))
+            })
+            .map(|i| i + 2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, Serialize)]
+    struct Meta {
+        title: String,
+        #[serde(default)]
+        count: Option<u32>,
+    }
+
+    fn split_ok(contents: &str) -> FrontMatter<'_> {
+        split(contents)
+            .expect("valid front matter")
+            .expect("front matter")
+    }
+
+    #[test]
+    fn no_front_matter() {
+        assert!(split("# Title\n\ntext\n").unwrap().is_none());
+        assert!(split("").unwrap().is_none());
+    }
+
+    #[test]
+    fn yaml_front_matter() {
+        let front_matter = split_ok("---\ntitle: A\n---\nbody\n");
+        assert_eq!(front_matter.format, Format::Yaml);
+        assert_eq!(front_matter.text, "title: A\n");
+        assert_eq!(front_matter.body, "body\n");
+        assert_eq!(front_matter.body_line, 4);
+    }
+
+    #[test]
+    fn toml_front_matter() {
+        let front_matter = split_ok("+++\ntitle = \"A\"\ncount = 2\n+++\nbody");
+        assert_eq!(front_matter.format, Format::Toml);
+        assert_eq!(front_matter.body, "body");
+        assert_eq!(front_matter.body_line, 5);
+        let meta: Meta = front_matter.parse().unwrap();
+        assert_eq!((meta.title.as_str(), meta.count), ("A", Some(2)));
+    }
+
+    #[test]
+    fn trailing_whitespace_on_delimiters() {
+        let front_matter = split_ok("--- \ntitle: A\n---\t\nbody");
+        assert_eq!(front_matter.text, "title: A\n");
+        assert_eq!(front_matter.body, "body");
+    }
+
+    #[test]
+    fn dashes_inside_values() {
+        let contents = "---\ntitle: A --- B\ndescription: |\n  ---\n---\nbody";
+        let front_matter = split_ok(contents);
+        assert_eq!(front_matter.text, "title: A --- B\ndescription: |\n  ---\n");
+        assert_eq!(front_matter.body, "body");
+        assert_eq!(front_matter.body_line, 6);
+    }
+
+    #[test]
+    fn unclosed_front_matter() {
+        // the other format's delimiter does not close it
+        let (position, message) = split("---\ntitle: A\n+++\nbody\n").unwrap_err();
+        assert_eq!(position, Position { line: 1, column: 1 });
+        assert_eq!(message, "front matter is not closed with a --- line");
+    }
+
+    #[test]
+    fn crlf_and_byte_order_mark() {
+        let contents = normalize("\u{feff}---\r\ntitle: A\r\n---\r\nline\r\nline\r\n");
+        let front_matter = split_ok(&contents);
+        assert_eq!(front_matter.text, "title: A\n");
+        assert_eq!(front_matter.body, "line\nline\n");
+    }
+
+    #[test]
+    fn yaml_error_position() {
+        let front_matter = split_ok("---\ntitle: A\ncount: many\n---\n");
+        let (position, message) = front_matter.parse::<Meta>().unwrap_err();
+        assert_eq!(position, Some(Position { line: 3, column: 8 }));
+        assert!(message.starts_with("count: invalid type"), "{message}");
+        assert!(!message.contains(" at line "), "{message}");
+    }
+
+    #[test]
+    fn yaml_error_message_without_locations() {
+        let front_matter = split_ok("---\ntitle: A\ntags: [a\n---\n");
+        let (position, message) = front_matter.parse::<Meta>().unwrap_err();
+        assert_eq!(position, Some(Position { line: 4, column: 1 }));
+        assert_eq!(message, "did not find expected ',' or ']', while parsing a flow sequence");
+        assert_eq!(strip_locations("no line at line end"), "no line at line end");
+    }
+
+    #[test]
+    fn toml_error_position() {
+        let front_matter = split_ok("+++\ntitle = \"A\"\ncount = \"many\"\n+++\n");
+        let (position, _) = front_matter.parse::<Meta>().unwrap_err();
+        assert_eq!(position, Some(Position { line: 3, column: 9 }));
+    }
+
+    #[test]
+    fn key_lines() {
+        let yaml = split_ok("---\ntitle: A\ncount: 2\n---\n");
+        assert_eq!(yaml.key_line("count"), Some(3));
+        assert_eq!(yaml.key_line("slug"), None);
+        let toml = split_ok("+++\ntitle = \"A\"\ncount = 2\n+++\n");
+        assert_eq!(toml.key_line("count"), Some(3));
+        // prefixes of other keys do not match
+        assert_eq!(toml.key_line("coun"), None);
+    }
+
+    #[test]
+    fn extra_keys() {
+        let front_matter = split_ok("---\ntitle: A\ntoc: true\n---\n");
+        let meta: Meta = front_matter.parse().unwrap();
+        let extra = front_matter.extra(&meta).unwrap();
+        assert_eq!(extra.keys().collect::<Vec<_>>(), ["toc"]);
+    }
+}
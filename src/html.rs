@@ -1,3 +1,4 @@
+use crate::config::SortOrder;
 use crate::image_convert;
 use crate::post::Post;
 use chrono::Datelike;
@@ -8,24 +9,37 @@ use minify_html::{minify, Cfg};
 use syntect::highlighting::ThemeSet;
 use syntect::html::highlighted_html_for_string;
 use syntect::parsing::SyntaxSet;
+use crate::error::{Error, Result};
+use std::error::Error as _;
+use std::path::Path;
 use tera::Tera;
 
 lazy_static! {
-    pub static ref TEMPLATES: Tera = {
-        let mut tera = match Tera::new("templates/**/*") {
-            Ok(t) => t,
-            Err(e) => {
-                println!("Parsing error(s): {}", e);
-                ::std::process::exit(1);
-            }
-        };
-        tera.autoescape_on(vec![".html"]);
-        tera
-    };
     static ref THEME_SET: ThemeSet = ThemeSet::load_defaults();
     static ref SYNTAX_SET: SyntaxSet = SyntaxSet::load_defaults_newlines();
 }
 
+/// Parse the templates in `templates_dir`.
+pub fn load_templates(templates_dir: &str) -> Result<Tera> {
+    let mut tera = Tera::new(&format!("{templates_dir}/**/*")).map_err(|e| Error::Template {
+        name: templates_dir.to_string(),
+        message: tera_error(&e),
+    })?;
+    tera.autoescape_on(vec![".html"]);
+    Ok(tera)
+}
+
+/// Tera error message including its causes, e.g. the undefined variable behind a render failure.
+pub fn tera_error(err: &tera::Error) -> String {
+    let mut message = err.to_string();
+    let mut source = err.source();
+    while let Some(e) = source {
+        message.push_str(&format!(": {e}"));
+        source = e.source();
+    }
+    message
+}
+
 pub fn minify_html(html: &String) -> String {
     let mut cfg = Cfg::spec_compliant();
     cfg.minify_css = true;
@@ -33,14 +47,16 @@ pub fn minify_html(html: &String) -> String {
     String::from_utf8(minified).expect("convert type")
 }
 
-pub fn minify_css(css: &str) -> String {
+/// Minified css, or the error message of invalid css.
+pub fn minify_css(css: &str) -> Result<String, String> {
     // nb: minify_css crashes with double ;
     Minifier::default()
         .minify(&css.replace(";;", ";"), Level::Three)
-        .expect("minified css")
+        .map_err(|e| format!("invalid css: {e}"))
 }
 
-pub fn create_figure(url: String, caption: Option<String>, scaling: Option<f64>) -> String {
+/// Figure for the image at `source`, `url` is where the converted image is served.
+pub fn create_figure(source: &Path, url: &str, caption: Option<String>, scaling: Option<f64>) -> String {
     let (caption_html, alt_text) = match caption {
         Some(s) => (format!("<figcaption>{}</figcaption>", s), s),
         None => ("".to_string(), "No description".to_string()),
@@ -48,12 +64,12 @@ pub fn create_figure(url: String, caption: Option<String>, scaling: Option<f64>)
 
     let scaling = scaling.unwrap_or(1.0);
 
-    let dims = image_convert::get_image_dims(&url);
+    let dims = image_convert::get_image_dims(source);
 
     let (width, height) = match dims {
         Ok(dim) => (dim.width, dim.height),
         Err(err) => {
-            println!("Failed to determine dimensions for {} : {}", url, err);
+            log::warn!("Failed to determine dimensions for {} : {}", source.display(), err);
             (100, 100)
         }
     };
@@ -64,11 +80,9 @@ pub fn create_figure(url: String, caption: Option<String>, scaling: Option<f64>)
     let height = height as usize;
     let width = width as usize;
 
-    let new_url: String = image_convert::modify_url(url);
-
     let figure = format!(
         r##"<figure>
-    <img src="{new_url}" width="{width}" height="{height}" alt="{alt_text}">
+    <img src="{url}" width="{width}" height="{height}" alt="{alt_text}">
     {caption_html}
     </figure>"##
     );
@@ -76,67 +90,166 @@ pub fn create_figure(url: String, caption: Option<String>, scaling: Option<f64>)
 }
 
 pub fn create_index(posts: &[Post]) -> String {
+    create_section_index(posts, SortOrder::Newest)
+}
+
+/// Posts in the order of an index page.
+pub fn sort_posts(posts: &[Post], order: SortOrder) -> Vec<&Post> {
+    match order {
+        SortOrder::Newest => posts.iter().sorted_by_key(|p| p.metadata.date).rev().collect(),
+        SortOrder::Oldest => posts.iter().sorted_by_key(|p| p.metadata.date).collect(),
+        SortOrder::Title => posts.iter().sorted_by_key(|p| p.metadata.title.to_lowercase()).collect(),
+    }
+}
+
+/// Index grouped by year for the date orders, a single list for `SortOrder::Title`.
+pub fn create_section_index(posts: &[Post], order: SortOrder) -> String {
     let mut index_content = "<dl>".to_string();
-    for (year, year_posts) in posts
-        .iter()
-        .sorted_by_key(|p| p.metadata.date)
-        .rev()
-        .group_by(|x| x.metadata.date.year().to_string())
-        .into_iter()
-    {
-        index_content.push_str(&format!("<dt> {} </dt> ", year));
-        for post in year_posts {
-            let link = format!(
-                r##"<dd><a href="/posts/{}">{}</a></dd>"##,
-                post.metadata.slug, post.metadata.title
-            );
-            index_content.push_str(&link);
+    let sorted = sort_posts(posts, order);
+    if order == SortOrder::Title {
+        index_content.extend(sorted.into_iter().map(index_entry));
+    } else {
+        for (year, year_posts) in sorted
+            .into_iter()
+            .group_by(|x| x.metadata.date.date_naive().year().to_string())
+            .into_iter()
+        {
+            index_content.push_str(&format!("<dt> {} </dt> ", year));
+            index_content.extend(year_posts.map(index_entry));
         }
     }
     index_content.push_str("</dl>");
     index_content
 }
 
-pub fn parse_equation(text: &String) -> (String, bool) {
+fn index_entry(post: &Post) -> String {
+    let draft = if post.metadata.draft { r#" <span class="draft">draft</span>"# } else { "" };
+    let reading_time = match post.reading_time {
+        0 => String::new(),
+        minutes => format!(r#" <span class="reading-time">{} min read</span>"#, minutes),
+    };
+    let summary = match post.summary.as_str() {
+        "" => String::new(),
+        summary => format!(r#"<p class="summary">{}</p>"#, tera::escape_html(summary)),
+    };
+    format!(
+        r##"<dd><a href="{}">{}</a>{}{}{}</dd>"##,
+        post.metadata.permalink, post.metadata.title, draft, reading_time, summary
+    )
+}
+
+/// Numbers of the parts of a series: their `part`, parts without one follow the part before them.
+pub fn part_numbers(parts: &[Post]) -> Vec<usize> {
+    let mut previous = 0;
+    parts
+        .iter()
+        .map(|p| {
+            previous = p.metadata.part.map_or(previous + 1, |part| part as usize);
+            previous
+        })
+        .collect()
+}
+
+/// Numbered list of the parts of a series, in order.
+pub fn create_series_index(parts: &[Post]) -> String {
+    let mut content = "<ol>".to_string();
+    for (post, number) in parts.iter().zip(part_numbers(parts)) {
+        let summary = match post.summary.as_str() {
+            "" => String::new(),
+            summary => format!(r#"<p class="summary">{}</p>"#, tera::escape_html(summary)),
+        };
+        content.push_str(&format!(
+            r##"<li value="{}"><a href="{}">{}</a>{}</li>"##,
+            number, post.metadata.permalink, post.metadata.title, summary
+        ));
+    }
+    content.push_str("</ol>");
+    content
+}
+
+/// Links to the `count` most recently updated posts, empty if no post was updated.
+pub fn create_recently_updated(posts: &[Post], count: usize) -> String {
+    let updated: Vec<&Post> = posts
+        .iter()
+        .filter(|p| p.metadata.updated.is_some())
+        .sorted_by_key(|p| p.metadata.updated)
+        .rev()
+        .take(count)
+        .collect();
+    if updated.is_empty() {
+        return String::new();
+    }
+    let mut content = "<dl>".to_string();
+    for post in updated {
+        let date = post.metadata.updated.map(|d| d.date_naive().to_string()).unwrap_or_default();
+        content.push_str(&format!(
+            r##"<dd><a href="{}">{}</a> {}</dd>"##,
+            post.metadata.permalink, post.metadata.title, date
+        ));
+    }
+    content.push_str("</dl>");
+    content
+}
+
+/// Page that sends visitors of an old url to `url`.
+pub fn create_redirect(url: &str) -> String {
+    format!(
+        r##"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Redirecting to {url}</title>
+<link rel="canonical" href="{url}">
+<meta name="robots" content="noindex">
+<meta http-equiv="refresh" content="0; url={url}">
+</head>
+<body>
+<a href="{url}">{url}</a>
+</body>
+</html>
+"##
+    )
+}
+
+/// Render the `$` delimited equations in `text` with KaTeX, also returns whether there were any.
+pub fn parse_equation(text: &str) -> Result<(String, bool), String> {
 
     if text.len() <= 2 {
-        (text.clone(), false)
+        Ok((text.to_string(), false))
     } else if text.starts_with("$$") && text.ends_with("$$") {
         // display mode equations
         if text.len() <= 4 {
-            println!("Invalid display mode equation, will skip: {}", text);
-            return (text.clone(), false);
+            log::warn!("Invalid display mode equation, will skip: {}", text);
+            return Ok((text.to_string(), false));
         }
 
         let slice = &text[2..text.len() - 2];
-        let is_valid = slice.find('$').is_none();
-        if !is_valid {
-            panic!("Invalid display (full) mode equation: {text}")
+        if slice.contains('$') {
+            return Err(format!("invalid display equation, it contains a $: {text}"));
         }
-        let opts = katex::Opts::builder().display_mode(true).build().unwrap();
-        let equation =
-            katex::render_with_opts(slice, opts).expect("rendered display mode equation");
+        let opts = katex::Opts::builder().display_mode(true).build().expect("katex options");
+        let equation = katex::render_with_opts(slice, opts).map_err(|e| format!("invalid equation {slice}: {e}"))?;
 
-        (equation, true)
+        Ok((equation, true))
     } else {
         // inline equations
         let matches: Vec<_> = text.match_indices('$').collect();
         let mut indices = Vec::new();
 
         if matches.len() <= 1 {
-            return (text.clone(), false);
+            Ok((text.to_string(), false))
         } else {
             // ignore dollar signs that are escaped
 
             for (i, _) in matches.iter() {
-                if i > &0 && text.chars().nth(i - 1).unwrap() == '\\' {
+                if text[..*i].ends_with('\\') {
                     continue;
                 } else {
                     indices.push(i);
                 }
             }
             if indices.len() % 2 != 0 {
-                panic!("Parsing went wrong for equation: {text}")
+                return Err(format!("unbalanced $ in equation, escape a literal dollar sign as \\$: {text}"));
             }
 
             let mut output = String::new();
@@ -161,9 +274,9 @@ pub fn parse_equation(text: &String) -> (String, bool) {
 
                 let equation = &text[start + 1..*end];
 
-                let opts = katex::Opts::builder().display_mode(false).build().unwrap();
-                let equation =
-                    katex::render_with_opts(equation, opts).expect("rendered inline equation");
+                let opts = katex::Opts::builder().display_mode(false).build().expect("katex options");
+                let equation = katex::render_with_opts(equation, opts)
+                    .map_err(|e| format!("invalid equation {equation}: {e}"))?;
                 output.push_str(&equation);
                 previous_index = *end;
 
@@ -173,12 +286,12 @@ pub fn parse_equation(text: &String) -> (String, bool) {
             if previous_index + 1 < text.len() {
                 output.push_str(&text[previous_index + 1..])
             }
-            (output, true)
+            Ok((output, true))
         }
     }
 }
 
-pub fn highlight_code(code: &str, language: Option<String>) -> String {
+pub fn highlight_code(code: &str, language: Option<String>) -> Result<String, String> {
     let syntax = match language {
         Some(s) => SYNTAX_SET.find_syntax_by_token(&s),
         None => None,
@@ -188,11 +301,11 @@ pub fn highlight_code(code: &str, language: Option<String>) -> String {
         Some(s) => s,
         None => match SYNTAX_SET.find_syntax_by_first_line(code) {
             Some(s) => {
-                println!("Code syntax determined from lines, consider adding annotation");
+                log::info!("Code syntax determined from lines, consider adding annotation");
                 s
             }
             None => {
-                println!("Failed to find code syntax");
+                log::warn!("Failed to find code syntax");
                 SYNTAX_SET.find_syntax_plain_text()
             }
         },
@@ -203,12 +316,13 @@ pub fn highlight_code(code: &str, language: Option<String>) -> String {
         syntax,
         &THEME_SET.themes["base16-eighties.dark"],
     )
-    .expect("parsed codeblock");
+    .map_err(|e| format!("failed to highlight code block: {e}"))?;
 
     // drop the included background color
-    let start = html.find('>').expect("background color style") + 1;
-    let end = html.find("</pre>").expect("background color style");
-    let html = &html[start..end].trim();
+    let (Some(start), Some(end)) = (html.find('>'), html.find("</pre>")) else {
+        return Err("failed to highlight code block: no <pre> in the highlighted html".to_string());
+    };
+    let html = &html[start + 1..end].trim();
     let html = format!(r##"<pre><code class="code-block">{}</code></pre>"##, html);
-    html
+    Ok(html)
 }
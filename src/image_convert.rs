@@ -1,35 +1,38 @@
+use crate::error::{Error, Result};
 use image::*;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use webp::*;
 
 const CONVERT_TO_WEBP: &[&str] = &["jpg", "jpeg", "png"];
 
 pub fn modify_url(url: String) -> String {
-    let mut new_url: String = url.replace("images/", "/images/");
-    let ext = new_url.rfind('.').unwrap();
+    converted_name(&url.replace("images/", "/images/"))
+}
 
-    if CONVERT_TO_WEBP.contains(&&new_url[ext + 1..]) {
-        new_url = format!("{}.webp", &new_url[..ext]);
+/// `name` with the extension of the converted file, images converted to webp end in `.webp`.
+pub fn converted_name(name: &str) -> String {
+    if let Some(ext) = name.rfind('.') {
+        if CONVERT_TO_WEBP.contains(&&name[ext + 1..]) {
+            return format!("{}.webp", &name[..ext]);
+        }
     }
-
-    new_url
+    name.to_string()
 }
 
-pub fn get_image_dims(url: &str) -> Result<imagesize::ImageSize, imagesize::ImageError> {
-    let dims = imagesize::size(format!("posts/{}", url));
-    dims
+pub fn get_image_dims(path: &Path) -> Result<imagesize::ImageSize, imagesize::ImageError> {
+    imagesize::size(path)
 }
 
-pub fn convert_image(source: PathBuf, dest: PathBuf, size_factor: f64) {
-    let ext = source.extension().unwrap();
-    let ext = ext.to_str().unwrap();
+pub fn convert_image(source: PathBuf, dest: PathBuf, size_factor: f64) -> Result<()> {
+    let ext = source.extension().unwrap_or_default().to_string_lossy().to_string();
+    let ext = ext.as_str();
 
     if CONVERT_TO_WEBP.contains(&ext) {
-        let mut img = image::open(&source).unwrap();
+        let mut img = image::open(&source).map_err(|e| format!("{}: {}", source.display(), e))?;
         let (w, h) = img.dimensions();
         if size_factor != 1.0 {
-            println!("Rescaling {:?}", &source);
+            log::info!("Rescaling {:?}", &source);
             img = image::DynamicImage::ImageRgba8(imageops::resize(
                 &img,
                 (w as f64 * size_factor) as u32,
@@ -37,18 +40,19 @@ pub fn convert_image(source: PathBuf, dest: PathBuf, size_factor: f64) {
                 imageops::FilterType::Gaussian,
             ));
         }
-        let encoder: Encoder = Encoder::from_image(&img).unwrap();
-        let webp: WebPMemory;
-        if ext == "png" {
-            webp = encoder.encode_lossless();
+        let encoder: Encoder =
+            Encoder::from_image(&img).map_err(|e| format!("{}: webp encoding failed: {}", source.display(), e))?;
+        let webp: WebPMemory = if ext == "png" {
+            encoder.encode_lossless()
         } else {
-            webp = encoder.encode(85f32)
+            encoder.encode(85f32)
         };
-        std::fs::write(&dest, &*webp).unwrap();
+        std::fs::write(&dest, &*webp).map_err(|e| Error::io(&dest, e))?;
     } else {
         if size_factor != 1.0 {
-            println!("Rescaling not supported for {ext}, skipping {source:?}")
+            log::warn!("Rescaling not supported for {ext}, skipping {source:?}")
         }
-        fs::copy(source, dest).expect("copy file");
+        fs::copy(&source, dest).map_err(|e| Error::io(&source, e))?;
     }
+    Ok(())
 }
@@ -0,0 +1,31 @@
+//! Static site generator for a blog.
+//!
+//! [`Site`] ties everything together, the modules expose the individual build stages.
+
+pub mod authors;
+pub mod build;
+pub mod cache;
+pub mod config;
+pub mod deps;
+pub mod error;
+pub mod front_matter;
+pub mod html;
+pub mod image_convert;
+pub mod markdown;
+pub mod page;
+pub mod post;
+pub mod rss;
+pub mod scaffold;
+pub mod serve;
+pub mod site;
+pub mod sitemap;
+pub mod watch;
+
+pub use config::SiteConfig;
+pub use error::{Error, Result};
+pub use markdown::{parse_markdown, Rendered};
+pub use page::StaticPage;
+pub use post::{Metadata, Post};
+pub use rss::generate_rss;
+pub use site::Site;
+pub use sitemap::generate_sitemap;
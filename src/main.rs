@@ -1,134 +1,187 @@
+use blog::config::{self, SiteConfig};
+use blog::watch::SiteWatcher;
+use blog::{scaffold, serve, Result, Site};
+use clap::{Parser, Subcommand};
 use fs_extra::dir::get_size;
-use rayon::prelude::*;
-use slug::slugify;
-use std::collections::HashMap;
-use std::fs;
+use std::io::Write;
 use std::path::PathBuf;
-use tera::Context;
-
-mod build;
-mod html;
-mod image_convert;
-mod markdown;
-mod post;
-mod rss;
-
-use post::Post;
-
-pub const BUILD_DIR: &str = "build";
-pub const MINIFY: bool = true;
-const BLOG_NAME: &str = "blog";
-const BLOG_URL: &str = "https://grobins.xyz"; 
-const CLEAN_BUILD: bool = true;
+use std::process::ExitCode;
+use std::time::Instant;
+
+#[derive(Parser)]
+#[command(about = "Static site generator for a blog")]
+struct Cli {
+    /// Site config file
+    #[arg(long, global = true, default_value = config::CONFIG_FILE)]
+    config: PathBuf,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
 
-fn process_post(path: &PathBuf) -> (Post, HashMap<String, f64>) {
-        let file_name = path.file_name().unwrap().to_str().unwrap();
+#[derive(Subcommand)]
+enum Command {
+    /// Build the site (default)
+    Build(BuildArgs),
+    /// Serve the site locally and rebuild on changes
+    Serve {
+        /// Port to listen on
+        #[arg(long, default_value_t = 1234)]
+        port: u16,
+    },
+    /// Parse and render every page without writing anything
+    Check,
+    /// Delete the build folder
+    Clean,
+    /// Create a new post in the posts folder
+    New {
+        /// Post title, the file name is derived from it
+        title: String,
+        /// Comma separated tags
+        #[arg(long, value_delimiter = ',')]
+        tags: Vec<String>,
+        /// Mark the post as a draft
+        #[arg(long)]
+        draft: bool,
+        /// Create a page bundle, a folder with `index.md` for the post and its images and files
+        #[arg(long)]
+        bundle: bool,
+        /// Section to add the post to, the posts folder if not given
+        #[arg(long)]
+        section: Option<String>,
+    },
+}
 
-        println!("Processing: {}", file_name);
+#[derive(clap::Args, Default)]
+struct BuildArgs {
+    /// Build folder, overrides `build_dir`
+    #[arg(long)]
+    output: Option<String>,
+    /// Skip html and css minification
+    #[arg(long)]
+    no_minify: bool,
+    /// Site url used in links and feeds, overrides `url`
+    #[arg(long)]
+    base_url: Option<String>,
+    /// Keep running and rebuild the pages affected by each change
+    #[arg(long)]
+    watch: bool,
+    /// Also build posts marked as drafts
+    #[arg(long)]
+    drafts: bool,
+    /// Also build posts dated in the future
+    #[arg(long)]
+    future: bool,
+}
 
-        let markdown_input = fs::read_to_string(path).unwrap();
+impl BuildArgs {
+    fn apply(&self, config: &mut SiteConfig) {
+        if let Some(output) = &self.output {
+            config.build_dir = output.clone();
+        }
+        if self.no_minify {
+            config.minify = false;
+        }
+        if let Some(url) = &self.base_url {
+            config.url = url.trim_end_matches('/').to_string();
+        }
+        if self.drafts {
+            config.drafts = true;
+        }
+        if self.future {
+            config.future = true;
+        }
+    }
+}
 
-        let post = Post::from_string(markdown_input).unwrap();
+fn build(mut config: SiteConfig, args: &BuildArgs) -> Result<()> {
+    let time = Instant::now();
+    args.apply(&mut config);
+    let mut site = Site::new(config)?;
+
+    if args.watch {
+        let mut watcher = SiteWatcher::new(site);
+        log::info!("Watching for changes");
+        loop {
+            watcher.wait_and_rebuild();
+        }
+    }
 
-        let (output, has_katex, post_image_scale) = post.render();
+    site.build()?;
 
-        let mut context = Context::new();
-        context.insert("title", &post.metadata.title);
-        context.insert("content", &output);
-        context.insert("has_katex", &has_katex);
-        context.insert("date", &post.metadata.date);
-        context.insert("tags", &post.metadata.tags);
-        context.insert("url", &BLOG_URL); 
+    let elapsed = time.elapsed();
+    log::info!("Done in: {:.2?}", elapsed);
 
-        build::build_html(&post.metadata.slug, "post.html", "/posts/", context);
+    let folder_size = get_size(&site.config.build_dir).map_err(|e| e.to_string())? / 1024;
+    log::info!("Build size: {}KB", folder_size);
+    Ok(())
+}
 
-        (post, post_image_scale)
+fn serve(config: SiteConfig, port: u16) -> Result<()> {
+    serve::serve(Site::new(serve::serve_config(config, port))?, port)
 }
 
-fn main() {
-    use std::time::Instant;
-    let time = Instant::now();
-        
-    let mut posts: Vec<Post> = Vec::new();
-    let mut image_scale: HashMap<String, f64> = HashMap::new();
-
-    build::init_build(CLEAN_BUILD);
-     // build posts
-    let markdown_files: Vec<PathBuf> = fs::read_dir("posts")
-        .unwrap()
-        .map(|result| result.unwrap().path())
-        .filter(|p| p.is_file() && p.extension().unwrap_or_else(|| std::ffi::OsStr::new("invalid")) == "md")
-        .collect();
-
-    let result: Vec<(Post, HashMap<String, f64>)> = markdown_files
-        .par_iter()
-        .map(process_post)
-        .collect();
-
-    for (post, scale) in result.into_iter() {
-        posts.push(post);
-        image_scale.extend(scale);
-    }
+fn check(config: SiteConfig) -> Result<()> {
+    let pages = Site::new(config)?.check()?;
+    log::info!("Checked {} pages", pages);
+    Ok(())
+}
 
-    // create index.html
-    let index_content = html::create_index(&posts);
-    let mut context = Context::new();
-    context.insert("content", &index_content);
-    context.insert("title", &BLOG_NAME);
-    context.insert("url", &BLOG_URL); 
-    build::build_html("index", "index.html", "/", context);
-
-    // about
-    let mut context = Context::new();
-    context.insert("title", &BLOG_NAME);
-    context.insert("url", &BLOG_URL); 
-
-    build::build_html("about", "about.html", "/", context);
-
-    // create tag indices
-    let mut unique_tags: HashMap<&str, Vec<Post>> = HashMap::new();
-    for post in &posts {
-        for tag in &post.metadata.tags {
-            if unique_tags.contains_key(tag as &str) {
-                let v: &mut Vec<Post> = unique_tags.get_mut(tag as &str).unwrap();
-                v.push(post.clone());
-            } else {
-                let v: Vec<Post> = vec![post.clone()];
-                unique_tags.insert(tag, v);
-            }
+fn clean(config: SiteConfig) -> Result<()> {
+    for config in [serve::serve_config(config.clone(), 0), config] {
+        if blog::build::clean(&config)? {
+            log::info!("Deleted {}", config.build_dir);
         }
     }
-    let mut all_tags = Vec::new();
-    for (tag, tag_posts) in unique_tags {
-        let index_content = html::create_index(&tag_posts);
-        let mut context = Context::new();
-        context.insert("content", &index_content);
-        context.insert("title", &tag);
-        context.insert("index_title", &tag);
-        context.insert("url", &BLOG_URL); 
-
-        build::build_html(&slugify(tag), "index.html", "/tags/", context);
-
-        all_tags.push(tag);
+    Ok(())
+}
+
+fn new(config: SiteConfig, title: &str, tags: &[String], draft: bool, bundle: bool, section: Option<&str>) -> Result<()> {
+    let post = scaffold::new_post(&config, title, tags, draft, bundle, section)?;
+    log::info!("Created {}", post.path.display());
+    for other in post.collisions {
+        log::warn!("{} has the same slug, both posts would be built to the same url", other.display());
     }
+    Ok(())
+}
 
-    // all tags index
-    all_tags.sort();
-    let mut context = Context::new();
-    context.insert("tags", &all_tags);
-    context.insert("title", "All tags");
-    context.insert("url", &BLOG_URL); 
-    build::build_html("all-tags", "all-tags.html", "/tags/", context);
-    // rss
-    let rss_xml = rss::generate_rss(&posts, BLOG_NAME, BLOG_URL); 
-    fs::write(format!("{}/rss.xml", BUILD_DIR), rss_xml).unwrap(); 
-    // assets
-    build::copy_assets("assets", BUILD_DIR);
-    build::process_images("posts/images", "/images/", &image_scale);
+/// Plain messages for info, level prefixed warnings and errors. `RUST_LOG` overrides the level.
+fn init_logger() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
+        .format(|buf, record| match record.level() {
+            log::Level::Info => writeln!(buf, "{}", record.args()),
+            level => writeln!(buf, "{}: {}", level, record.args()),
+        })
+        .init();
+}
 
-    let elapsed = time.elapsed();
-    println!("Done in: {:.2?}", elapsed);
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    init_logger();
 
-    let folder_size = get_size(BUILD_DIR).unwrap() / 1024;
-    println!("Build size: {}KB", folder_size);
+    let config = match SiteConfig::load(&cli.config) {
+        Ok(c) => c,
+        Err(e) => {
+            log::error!("Invalid config: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let result = match cli.command.unwrap_or(Command::Build(BuildArgs::default())) {
+        Command::Build(args) => build(config, &args),
+        Command::Serve { port } => serve(config, port),
+        Command::Check => check(config),
+        Command::Clean => clean(config),
+        Command::New { title, tags, draft, bundle, section } => {
+            new(config, &title, &tags, draft, bundle, section.as_deref())
+        }
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            log::error!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
 }
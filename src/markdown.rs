@@ -1,68 +1,224 @@
 use itertools::{Itertools, MultiPeek};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use pulldown_cmark::{
     html as pdc_html, CodeBlockKind, CowStr, Event, Options, Parser, Tag,
 };
 
+use crate::error::{Error, Position};
 use crate::html;
+use crate::image_convert;
+use serde::{Deserialize, Serialize};
 
-pub struct EventIterator<'a, I: Iterator<Item = Event<'a>>> {
+/// Output of `parse_markdown`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Rendered {
+    pub html: String,
+    pub has_katex: bool,
+    /// Image source file -> scale factor requested with `{width=x%}`
+    pub image_scale: HashMap<PathBuf, f64>,
+    /// Source files of every embedded image
+    pub images: Vec<PathBuf>,
+    /// Words of the text, code blocks and equations are not counted
+    pub word_count: usize,
+}
+
+/// Reading speed used to estimate reading times
+pub const WORDS_PER_MINUTE: usize = 200;
+
+/// Estimated minutes to read `word_count` words, at least one.
+pub fn reading_time(word_count: usize) -> usize {
+    word_count.div_ceil(WORDS_PER_MINUTE).max(1)
+}
+
+/// Where the relative urls of a post point to.
+#[derive(Debug, Clone, Copy)]
+pub struct Location<'a> {
+    /// Urls of posts that are single files are resolved against the posts folder,
+    /// `images/x.jpg` is served as `/images/x.webp`
+    pub posts_dir: &'a Path,
+    /// Folder of a page bundle and the url of its output folder, relative urls of
+    /// bundles are resolved against the bundle
+    pub bundle: Option<(&'a Path, &'a str)>,
+}
+
+/// Urls pointing at a file next to the markdown, not at another site or an absolute path.
+fn is_relative(url: &str) -> bool {
+    !url.is_empty() && !url.contains(':') && !url.starts_with(['/', '#', '?'])
+}
+
+/// Events within a run of text, a word can go on after them: text and inline markup.
+fn continues_words(event: &Event) -> bool {
+    match event {
+        Event::Text(_) | Event::Code(_) => true,
+        Event::Start(tag) | Event::End(tag) => {
+            matches!(tag, Tag::Emphasis | Tag::Strong | Tag::Strikethrough | Tag::Link(..))
+        }
+        _ => false,
+    }
+}
+
+/// Invalid markdown, `offset` is the byte offset in the markdown where the problem starts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarkdownError {
+    pub offset: usize,
+    pub message: String,
+}
+
+impl MarkdownError {
+    /// Error in the file `path` whose markdown, `markdown`, starts on line `first_line`.
+    pub fn in_file(self, path: &Path, markdown: &str, first_line: usize) -> Error {
+        let position = Position::from_offset(markdown, self.offset);
+        let mut error = Error::source(path, markdown, Some(position), self.message);
+        if let Error::Source { position, .. } = &mut error {
+            *position = position.map(|p| p.offset_lines(first_line));
+        }
+        error
+    }
+}
+
+/// Wraps a parser with source offsets (`Parser::into_offset_iter`). Iteration stops at the
+/// first invalid construct, which is then available in `error`.
+pub struct EventIterator<'a, I: Iterator<Item = (Event<'a>, Range<usize>)>> {
     parser: MultiPeek<I>,
     has_katex: bool,
-    image_scale: HashMap<String, f64>,
+    image_scale: HashMap<PathBuf, f64>,
+    images: Vec<PathBuf>,
+    word_count: usize,
+    /// Text since the last event that ends a word, see `count_words`
+    words: String,
+    location: Location<'a>,
+    /// Start of the last event taken from the parser
+    offset: usize,
+    error: Option<MarkdownError>,
 }
 
-impl<'a, I: Iterator<Item = Event<'a>>> EventIterator<'a, I> {
-    pub fn new(parser: I) -> Self {
+impl<'a, I: Iterator<Item = (Event<'a>, Range<usize>)>> EventIterator<'a, I> {
+    pub fn new(parser: I, location: Location<'a>) -> Self {
         Self {
             parser: parser.multipeek(),
             has_katex: false,
             image_scale: HashMap::new(),
+            images: Vec::new(),
+            word_count: 0,
+            words: String::new(),
+            location,
+            offset: 0,
+            error: None,
         }
     }
 
+    fn next_event(&mut self) -> Option<Event<'a>> {
+        let (event, range) = self.parser.next()?;
+        self.offset = range.start;
+        Some(event)
+    }
+
+    /// Stop iterating with an error at `offset`.
+    fn fail(&mut self, offset: usize, message: impl Into<String>) -> Option<Event<'a>> {
+        self.error = Some(MarkdownError { offset, message: message.into() });
+        None
+    }
+
+    pub fn error(&self) -> Option<&MarkdownError> {
+        self.error.as_ref()
+    }
+
     pub fn enable_katex(&mut self) {
         self.has_katex = true;
     }
 
-    pub fn add_image(&mut self, url: String, scaling: f64) {
-        self.image_scale.insert(url, scaling);
+    pub fn add_image(&mut self, source: PathBuf, scaling: f64) {
+        self.image_scale.insert(source, scaling);
+    }
+
+    /// Source file and served url of an embedded image. Bundles can still embed
+    /// `images/x.jpg` of the posts folder if they have no such file themselves.
+    fn resolve_image(&self, url: &str) -> (PathBuf, String) {
+        match self.location.bundle {
+            Some((dir, folder_url)) if is_relative(url) && dir.join(url).is_file() => {
+                (dir.join(url), format!("{folder_url}{}", image_convert::converted_name(url)))
+            }
+            _ => (self.location.posts_dir.join(url), image_convert::modify_url(url.to_string())),
+        }
+    }
+
+    /// Url a link to a file of the bundle is served at, `None` for other links.
+    fn resolve_link(&self, url: &str) -> Option<String> {
+        let (dir, folder_url) = self.location.bundle?;
+        let file = url.split(['#', '?']).next().unwrap_or_default();
+        if !is_relative(url) || !dir.join(file).is_file() {
+            return None;
+        }
+        Some(format!("{folder_url}{}", image_convert::converted_name(file)) + &url[file.len()..])
+    }
+
+    /// Words are counted once they end, pulldown-cmark splits text at quotes and entities so
+    /// `don't` arrives in several text events.
+    fn count_words(&mut self, text: &str) {
+        self.words.push_str(text);
+    }
+
+    fn end_words(&mut self) {
+        self.word_count += self.words.split_whitespace().count();
+        self.words.clear();
+    }
+
+    fn equation(&mut self, start: usize, text: &str) -> Option<Event<'a>> {
+        match html::parse_equation(text) {
+            Ok((equation, flag)) => {
+                if flag {self.enable_katex()}
+                Some(Event::Html(equation.into()))
+            }
+            Err(e) => self.fail(start, e),
+        }
     }
 }
 
-impl<'a, I: Iterator<Item = Event<'a>>> Iterator for EventIterator<'a, I> {
+impl<'a, I: Iterator<Item = (Event<'a>, Range<usize>)>> Iterator for EventIterator<'a, I> {
     type Item = Event<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(event) = self.parser.next() {
+        if self.error.is_some() {
+            return None;
+        }
+        if let Some(event) = self.next_event() {
+            let start = self.offset;
+            if !continues_words(&event) {
+                self.end_words();
+            }
             match event {
                 // images + figures
                 Event::Start(Tag::Image(_link_type, url, _title)) => {
-                    
-                    let caption = match &self.parser.next() {
+                    let (source, served_url) = self.resolve_image(&url);
+                    self.images.push(source.clone());
+
+                    let caption = match &self.next_event() {
                         Some(Event::Text(t)) => {
-                            self.parser.next();
+                            self.next_event();
                             Some(t.to_owned().to_string())
                         },
                         _ => None,
                     };
 
                     // extract width=x%.
-                    let scaling = match &self.parser.peek() {
-                        Some(Event::Text(t)) => {
+                    let scaling = match self.parser.peek() {
+                        Some((Event::Text(t), range)) => {
                             let s = t.replace(' ', "");
                             let pattern = "width=";
                             if s.starts_with('{') && s.ends_with('}') && s.contains(pattern) {
-                                let start = s.find('=').expect("width specified with =") + 1;
-                                let end = s.find('%').expect("width specified with %");
-                                let s = &s[start..end];
-                                let scaling: usize = s.parse().expect("number conversion");
+                                let offset = range.start;
+                                let width = s
+                                    .split_once('=')
+                                    .and_then(|(_, w)| w.strip_suffix("%}"))
+                                    .and_then(|w| w.parse::<usize>().ok());
+                                let Some(scaling) = width else {
+                                    return self.fail(offset, format!("invalid image width {s}, expected {{width=x%}}"));
+                                };
                                 let scaling = (scaling as f64) / 100.0;
-                                self.parser.next();
-
-                                let key = PathBuf::from(url.to_string()).file_name().unwrap().to_str().unwrap().to_string(); 
-                                self.add_image(key, scaling);
+                                self.next_event();
+                                self.add_image(source.clone(), scaling);
 
                                 Some(scaling)
                             } else {
@@ -72,16 +228,16 @@ impl<'a, I: Iterator<Item = Event<'a>>> Iterator for EventIterator<'a, I> {
                         _ => None,
                     };
 
-                    let html = html::create_figure(url.clone().to_string(), caption, scaling);
+                    let html = html::create_figure(&source, &served_url, caption, scaling);
 
-                    return Some(Event::Html(html.into()));
+                    Some(Event::Html(html.into()))
                 }
                 // code blocks
                 Event::Start(Tag::CodeBlock(_block)) => {
                     let mut buffer = String::new();
 
                     loop {
-                        let next = self.parser.next();
+                        let next = self.next_event();
                         if let Some(Event::Text(text)) = next {
                             buffer.push_str(&text);
                         } else if let Some(Event::End(Tag::CodeBlock(kind))) = next {
@@ -89,62 +245,179 @@ impl<'a, I: Iterator<Item = Event<'a>>> Iterator for EventIterator<'a, I> {
                                 CodeBlockKind::Fenced(lang) => Some(lang.to_string()),
                                 CodeBlockKind::Indented => None,
                             };
-                            let html = html::highlight_code(&buffer, language);
-
-                            return Some(Event::Html(CowStr::from(html)));
+                            return match html::highlight_code(&buffer, language) {
+                                Ok(html) => Some(Event::Html(CowStr::from(html))),
+                                Err(e) => self.fail(start, e),
+                            };
                         } else {
-                            panic!("Code block end was not received")
+                            return self.fail(start, "code block is not terminated");
                         }
                     }
                 }
                 Event::Text(text) => {
                     if !text.contains('$') {
                         // no equation
+                        self.count_words(&text);
                         Some(Event::Text(text))
                     } 
                     else if text.trim().starts_with("$$") {
                         // multi line equation
                         let mut buffer = text.to_string();
-                        loop {
-                            let next = self.parser.next();
-                            match next {
-                                Some(e) => match e {
-                                    Event::Text(text) => {
-                                        buffer.push_str(&text);
-                                        if text.trim().ends_with("$$") {
-                                            let (equation, flag) = html::parse_equation(&buffer);
-                                            if flag {self.enable_katex()}
-                                            return Some(Event::Html(equation.into()))
-                                        }
-                                    }
-                                    _ => (),
-                                },
-                                None => panic!("Multi line equation wasnt terminated"),
+                        // a single text event can hold the whole equation
+                        let mut terminated = buffer.trim().len() > 2 && buffer.trim().ends_with("$$");
+                        while !terminated {
+                            match self.next_event() {
+                                Some(Event::Text(text)) => {
+                                    buffer.push_str(&text);
+                                    terminated = text.trim().ends_with("$$");
+                                }
+                                Some(_) => (),
+                                None => return self.fail(start, "display equation is not terminated with $$"),
                             }
                         }
+                        self.equation(start, buffer.trim())
                     } 
                     else {
-                        let (equation, flag) = html::parse_equation(&text.trim().to_string());
-                        if flag {self.enable_katex()}
-                        Some(Event::Html(equation.into()))
+                        self.count_words(&strip_equations(&text));
+                        self.equation(start, text.trim())
                     }
                 }
+                Event::Start(Tag::Link(link_type, url, title)) => match self.resolve_link(&url) {
+                    Some(served_url) => Some(Event::Start(Tag::Link(link_type, served_url.into(), title))),
+                    None => Some(Event::Start(Tag::Link(link_type, url, title))),
+                },
+                Event::Code(text) => {
+                    self.count_words(&text);
+                    Some(Event::Code(text))
+                }
                 _ => Some(event),
             }
         } else {
+            self.end_words();
             None
         }
     }
 }
 
-pub fn parse_markdown(markdown: &str) -> (String, bool, HashMap<String, f64>) {
-    let parser = Parser::new_ext(markdown, Options::all());
-    let mut iterator = EventIterator::new(parser);
+/// Marker that ends the excerpt of a post
+pub const MORE_MARKER: &str = "<!-- more -->";
+/// Excerpts taken from the first paragraph are cut to about this many characters
+pub const EXCERPT_LENGTH: usize = 300;
+
+/// Remove `$` delimited equations, `\$` is kept as a dollar sign.
+fn strip_equations(text: &str) -> String {
+    let mut output = String::new();
+    let mut in_equation = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'$') => {
+                chars.next();
+                if !in_equation {
+                    output.push('$');
+                }
+            }
+            '$' => {
+                // $$ delimits display equations
+                if chars.peek() == Some(&'$') {
+                    chars.next();
+                }
+                in_equation = !in_equation;
+            }
+            c if !in_equation => output.push(c),
+            _ => (),
+        }
+    }
+    output
+}
+
+/// Plain text summary of a post: everything before `<!-- more -->`, or the first paragraph
+/// with text cut to about `EXCERPT_LENGTH` characters. Headings, code blocks, equations and images are left out.
+pub fn excerpt(markdown: &str) -> String {
+    let has_marker = markdown.lines().any(|l| l.trim() == MORE_MARKER);
+    let mut text = String::new();
+    // text of the current paragraph, equations are removed once it is complete
+    let mut paragraph = String::new();
+    let mut depth = 0;
+    let mut skip = 0;
+    for (event, range) in Parser::new_ext(markdown, Options::all()).into_offset_iter() {
+        match event {
+            Event::Html(html) if html.trim() == MORE_MARKER => break,
+            Event::Start(Tag::CodeBlock(_) | Tag::Image(..) | Tag::Heading(..) | Tag::FootnoteDefinition(_)) => skip += 1,
+            Event::End(Tag::CodeBlock(_) | Tag::Image(..) | Tag::Heading(..) | Tag::FootnoteDefinition(_)) => skip -= 1,
+            Event::Start(Tag::Paragraph | Tag::Item) => depth += 1,
+            Event::End(Tag::Paragraph | Tag::Item) => {
+                depth -= 1;
+                text.push_str(&strip_equations(&paragraph));
+                text.push(' ');
+                paragraph.clear();
+                if !has_marker && depth == 0 && !text.trim().is_empty() {
+                    break;
+                }
+            }
+            Event::Text(t) if skip == 0 => {
+                // markdown drops the backslash of an escaped dollar sign
+                if markdown[..range.start].ends_with('\\') {
+                    paragraph.push('\\');
+                }
+                paragraph.push_str(&t)
+            }
+            Event::Code(t) if skip == 0 => paragraph.push_str(&t.replace('$', "\\$")),
+            Event::SoftBreak | Event::HardBreak => paragraph.push(' '),
+            _ => (),
+        }
+    }
+    let text = text.split_whitespace().join(" ");
+    if has_marker || text.chars().count() <= EXCERPT_LENGTH {
+        return text;
+    }
+    let cut: String = text.chars().take(EXCERPT_LENGTH).collect();
+    let cut = cut.rsplit_once(' ').map(|(start, _)| start).unwrap_or(&cut);
+    format!("{}…", cut.trim_end_matches(|c: char| c.is_ascii_punctuation()))
+}
+
+pub fn parse_markdown(markdown: &str, location: Location) -> Result<Rendered, MarkdownError> {
+    let parser = Parser::new_ext(markdown, Options::all()).into_offset_iter();
+    let mut iterator = EventIterator::new(parser, location);
     let mut html = String::new();
 
     pdc_html::push_html(&mut html, &mut iterator);
-    let has_katex = iterator.has_katex;
-    let image_scale = iterator.image_scale.clone();
+    if let Some(error) = iterator.error {
+        return Err(error);
+    }
+
+    Ok(Rendered {
+        html,
+        has_katex: iterator.has_katex,
+        image_scale: iterator.image_scale,
+        images: iterator.images,
+        word_count: iterator.word_count,
+    })
+}
 
-    (html, has_katex, image_scale)
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word_count(markdown: &str) -> usize {
+        let location = Location { posts_dir: Path::new("posts"), bundle: None };
+        parse_markdown(markdown, location).unwrap().word_count
+    }
+
+    #[test]
+    fn words_split_by_the_parser() {
+        assert_eq!(word_count("I've don't won't it's a \"quoted\" word &amp; more."), 9);
+        assert_eq!(word_count("I've don't won't it's a \"quoted\" word & more."), 9);
+    }
+
+    #[test]
+    fn words_across_markup_and_lines() {
+        assert_eq!(word_count("un*believ*able **bold** `code` [a link](/x/)"), 5);
+        assert_eq!(word_count("one\ntwo\n\n# three\n\n- four\n- five"), 5);
+    }
+
+    #[test]
+    fn code_blocks_and_equations_are_not_words() {
+        assert_eq!(word_count("one $x + y$ two\n\n```\nlet x = 1;\n```\n\n$$\na + b\n$$"), 2);
+    }
+}
@@ -0,0 +1,90 @@
+use crate::config::SiteConfig;
+use crate::error::{Error, Position, Result};
+use crate::front_matter;
+use crate::markdown::{self, Location, Rendered};
+use crate::post;
+use serde::{Deserialize, Serialize};
+use slug::slugify;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Front matter of a standalone page.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct PageMetadata {
+    pub title: String,
+    /// Meta description of the page
+    #[serde(default)]
+    pub description: Option<String>,
+    /// The page is served at `/{slug}/`, derived from the file name unless given
+    #[serde(default)]
+    pub slug: String,
+    /// Position in the navigation menu, pages without one are not in the menu
+    #[serde(default)]
+    pub menu: Option<i32>,
+    /// Template under `SiteConfig::templates_dir` to render the page with
+    #[serde(default)]
+    pub template: Option<String>,
+    /// Every other front matter key, for templates
+    #[serde(default, skip_deserializing)]
+    pub extra: BTreeMap<String, tera::Value>,
+}
+
+/// A markdown file of `SiteConfig::pages_dir`, rendered like a post but not listed with them.
+#[derive(Debug, PartialEq, Serialize, Clone)]
+pub struct StaticPage {
+    pub metadata: PageMetadata,
+    pub contents: String,
+    #[serde(skip)]
+    pub path: PathBuf,
+    /// Line of the source file `contents` starts on
+    #[serde(skip)]
+    pub content_line: usize,
+}
+
+impl StaticPage {
+    /// Parse the contents of the page at `path`, pages need front matter.
+    pub fn parse(path: &Path, contents: &str) -> Result<StaticPage> {
+        let contents = &front_matter::normalize(contents);
+        let error = |position: Option<Position>, message: String| Error::source(path, contents, position, message);
+        let start = Some(Position { line: 1, column: 1 });
+
+        let front_matter = front_matter::split(contents)
+            .map_err(|(position, message)| error(Some(position), message))?
+            .ok_or_else(|| error(start, "pages must start with --- or +++ front matter".to_string()))?;
+        let parse_error = |(position, message): (Option<Position>, String)| error(position.or(start), message);
+        let mut metadata: PageMetadata = front_matter.parse().map_err(parse_error)?;
+        metadata.extra = front_matter.extra(&metadata).map_err(parse_error)?;
+
+        metadata.slug = metadata.slug.trim().to_string();
+        if metadata.slug.is_empty() {
+            metadata.slug = slugify(path.file_stem().unwrap_or_default().to_string_lossy());
+        }
+        if let Err(message) = post::check_slug(&metadata.slug) {
+            let position = front_matter.key_line("slug").map(|line| Position { line, column: 1 });
+            return Err(error(position, message));
+        }
+        Ok(StaticPage {
+            metadata,
+            contents: front_matter.body.to_string(),
+            path: path.to_path_buf(),
+            content_line: front_matter.body_line,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<StaticPage> {
+        let contents = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+        StaticPage::parse(path, &contents)
+    }
+
+    /// Site relative url, pages are top level: `/{slug}/`.
+    pub fn url(&self) -> String {
+        format!("/{}/", self.metadata.slug)
+    }
+
+    /// Render the markdown, `images/x.jpg` refers to the images of the posts folder like in posts.
+    pub fn render(&self, config: &SiteConfig) -> Result<Rendered> {
+        let location = Location { posts_dir: Path::new(&config.posts_dir), bundle: None };
+        markdown::parse_markdown(&self.contents, location).map_err(|e| e.in_file(&self.path, &self.contents, self.content_line))
+    }
+}
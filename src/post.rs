@@ -1,54 +1,488 @@
-use std::{error::Error, collections::HashMap};
-use serde::{Serialize, Deserialize};
-use chrono::NaiveDate;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveTime, Utc};
 use slug::slugify;
-use crate::BLOG_URL;
+use crate::config::SiteConfig;
+use crate::error::{Error, Position, Result};
+use crate::front_matter;
 
-use crate::markdown; 
+use crate::markdown::{self, Location, Rendered};
+
+/// Publication date: a plain date (`2023-01-31`, midnight UTC) or a date and time with a
+/// timezone offset (`2023-01-31T09:00:00+02:00`, `2023-01-31 09:00 +02:00`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PostDate {
+    pub datetime: DateTime<FixedOffset>,
+    /// Whether a time was given, plain dates are shown without one
+    pub has_time: bool,
+}
+
+impl PostDate {
+    pub fn parse(s: &str) -> Result<PostDate, String> {
+        let s = s.trim();
+        if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+            let datetime = date.and_time(NaiveTime::MIN).and_utc().fixed_offset();
+            return Ok(PostDate { datetime, has_time: false });
+        }
+        DateTime::parse_from_rfc3339(s)
+            .or_else(|_| DateTime::parse_from_str(s, "%Y-%m-%d %H:%M %:z"))
+            .or_else(|_| DateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S %:z"))
+            .map(|datetime| PostDate { datetime, has_time: true })
+            .map_err(|_| format!("invalid date {s:?}, expected 2023-01-31 or 2023-01-31T09:00:00+02:00"))
+    }
+
+    /// The calendar date in the timezone it was written in.
+    pub fn date_naive(&self) -> NaiveDate {
+        self.datetime.date_naive()
+    }
+
+    /// Whether the date is later than `now`. Plain dates are compared by calendar day with the
+    /// local date, `new` writes today's local date and it should be published right away.
+    pub fn is_after(&self, now: DateTime<Local>) -> bool {
+        if self.has_time {
+            self.datetime > now
+        } else {
+            self.date_naive() > now.date_naive()
+        }
+    }
+
+    pub fn to_utc(&self) -> DateTime<Utc> {
+        self.datetime.to_utc()
+    }
+}
+
+impl From<NaiveDate> for PostDate {
+    fn from(date: NaiveDate) -> PostDate {
+        PostDate { datetime: date.and_time(NaiveTime::MIN).and_utc().fixed_offset(), has_time: false }
+    }
+}
+
+impl Ord for PostDate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.datetime.cmp(&other.datetime)
+    }
+}
+
+impl PartialOrd for PostDate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for PostDate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.has_time {
+            write!(f, "{}", self.datetime.to_rfc3339())
+        } else {
+            write!(f, "{}", self.date_naive())
+        }
+    }
+}
+
+impl Serialize for PostDate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+struct PostDateVisitor;
+
+impl<'de> serde::de::Visitor<'de> for PostDateVisitor {
+    type Value = PostDate;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a date or a date and time with a timezone")
+    }
+
+    // parsing in the visitor lets the deserializer point at the invalid value
+    fn visit_str<E: serde::de::Error>(self, s: &str) -> Result<PostDate, E> {
+        PostDate::parse(s).map_err(E::custom)
+    }
+
+    // toml dates are not strings
+    fn visit_map<A: serde::de::MapAccess<'de>>(self, map: A) -> Result<PostDate, A::Error> {
+        let datetime = toml::value::Datetime::deserialize(serde::de::value::MapAccessDeserializer::new(map))?;
+        PostDate::parse(&datetime.to_string()).map_err(serde::de::Error::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for PostDate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<PostDate, D::Error> {
+        deserializer.deserialize_str(PostDateVisitor)
+    }
+}
+
+/// Markdown file of a page bundle
+pub const BUNDLE_INDEX: &str = "index.md";
+
+/// Whether `path` is the `index.md` of a page bundle. An `index.md` right in a section folder is
+/// a single file post, the section folder is not its bundle.
+pub fn is_bundle_index(config: &SiteConfig, path: &Path) -> bool {
+    path.file_name().is_some_and(|name| name == BUNDLE_INDEX)
+        && path.parent() != Some(Path::new(&config.section_of(path).dir))
+}
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub struct Metadata{
     pub title: String, 
-    pub date: NaiveDate, 
+    /// Summary for listings, meta tags and the feed, an excerpt of the post is used when not given
+    #[serde(default)]
+    pub description: Option<String>,
+    pub date: PostDate, 
+    /// Last significant change. Taken from the last git commit of the file when not given
+    /// and `SiteConfig::updated_from_git` is set
+    #[serde(default)]
+    pub updated: Option<PostDate>,
+    /// Derived from the title unless given
+    #[serde(default)]
     pub slug: String, 
     pub tags: Vec<String>,
+    /// Name of the series the post is a part of
+    #[serde(default)]
+    pub series: Option<String>,
+    /// Position in the series, parts without one follow the numbered parts by date
+    #[serde(default)]
+    pub part: Option<u32>,
+    /// Ids of the authors in `SiteConfig::authors_file`
+    #[serde(default)]
+    pub authors: Vec<String>,
+    /// Site relative url from the permalink of the post's section, e.g. `/posts/my-post/`
+    #[serde(default, skip_deserializing)]
+    pub permalink: String,
+    #[serde(default, skip_deserializing)]
     pub url: String, 
+    /// Previous urls of the post, they redirect to the permalink
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// Template under `SiteConfig::templates_dir` to render the post with
+    #[serde(default)]
+    pub template: Option<String>,
+    /// Drafts are only built with `SiteConfig::drafts`
+    #[serde(default)]
+    pub draft: bool,
+    /// Every other front matter key, for templates
+    #[serde(default, skip_deserializing)]
+    pub extra: BTreeMap<String, tera::Value>,
+    /// Taxonomy name -> terms of the post, read from the front matter key of each `SiteConfig::taxonomies`
+    #[serde(default, skip_deserializing)]
+    pub taxonomies: BTreeMap<String, Vec<String>>,
 }
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub struct Post {
     pub metadata: Metadata,
     pub contents: String, 
+    /// Source file, empty for posts not loaded from disk
+    #[serde(skip)]
+    pub path: PathBuf,
+    /// Name of the section the post belongs to, see `SiteConfig::section_of`
+    #[serde(default, skip_deserializing)]
+    pub section: String,
+    /// Line of the source file `contents` starts on
+    #[serde(skip)]
+    pub content_line: usize,
+    /// `description`, or the excerpt of the post
+    #[serde(skip_deserializing)]
+    pub summary: String,
+    /// Words of the rendered post, known once it is rendered
+    #[serde(skip_deserializing)]
+    pub word_count: usize,
+    /// Estimated minutes to read the post
+    #[serde(skip_deserializing)]
+    pub reading_time: usize,
+    /// Whether the post is the `index.md` of a page bundle, see `is_bundle_index`
+    #[serde(skip)]
+    pub bundle: bool,
+}
+
+/// Expand the `:year`, `:month`, `:day` and `:slug` placeholders of a permalink pattern.
+pub fn permalink(pattern: &str, metadata: &Metadata) -> String {
+    let date = metadata.date.date_naive();
+    pattern
+        .replace(":year", &date.format("%Y").to_string())
+        .replace(":month", &date.format("%m").to_string())
+        .replace(":day", &date.format("%d").to_string())
+        .replace(":slug", &metadata.slug)
+}
+
+/// Metadata of a post without front matter: the title from a leading `# Title` heading, which is
+/// removed from the markdown, and the date and slug from a `2024-01-31-my-post.md` file name.
+/// Also returns the markdown and the line it starts on.
+fn infer_metadata<'a>(contents: &'a str, path: &Path) -> Option<(Metadata, &'a str, usize)> {
+    let stem = path.file_stem()?.to_str()?;
+    let date = NaiveDate::parse_from_str(stem.get(..10)?, "%Y-%m-%d").ok()?;
+    let slug = slugify(stem[10..].strip_prefix('-').unwrap_or_default());
+
+    let (heading_line, heading) = contents.lines().enumerate().find(|(_, line)| !line.trim().is_empty())?;
+    let title = heading.strip_prefix("# ")?.trim().trim_end_matches('#').trim_end();
+    let body_start = contents.split_inclusive('\n').take(heading_line + 1).map(str::len).sum();
+
+    let metadata = Metadata {
+        title: title.to_string(),
+        description: None,
+        date: date.into(),
+        updated: None,
+        slug: slug.to_string(),
+        tags: Vec::new(),
+        series: None,
+        part: None,
+        authors: Vec::new(),
+        permalink: String::new(),
+        url: String::new(),
+        aliases: Vec::new(),
+        template: None,
+        draft: false,
+        extra: BTreeMap::new(),
+        taxonomies: BTreeMap::new(),
+    };
+    Some((metadata, &contents[body_start..], heading_line + 2))
+}
+
+/// Whether every segment of the relative path `path` is a plain name, no `..`, `.` or root, so
+/// what is written to it stays in its folder.
+pub fn is_plain_path(path: &str) -> bool {
+    let path = Path::new(path);
+    path.components().next().is_some() && path.components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+/// Error message if `slug` is not a single plain path segment.
+pub fn check_slug(slug: &str) -> std::result::Result<(), String> {
+    if slug.contains('/') {
+        Err("slug cannot contain /".to_string())
+    } else if !is_plain_path(slug) {
+        Err(format!("slug {slug:?} is not a valid file name"))
+    } else {
+        Ok(())
+    }
 }
 
+/// Terms of a taxonomy front matter value: a string or a list of strings, blank terms are left out.
+fn terms(value: &tera::Value) -> Option<Vec<String>> {
+    let values = match value {
+        tera::Value::Array(values) => values.iter().collect(),
+        value => vec![value],
+    };
+    let terms = values.into_iter().map(|v| v.as_str().map(|t| t.trim().to_string())).collect::<Option<Vec<_>>>()?;
+    Some(terms.into_iter().filter(|t| !t.is_empty()).collect())
+}
+
+/// Date of the last commit touching `path` in the local git repository.
+pub fn git_updated(path: &Path) -> Option<PostDate> {
+    let output = Command::new("git").args(["log", "-1", "--format=%cI", "--"]).arg(path).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    PostDate::parse(String::from_utf8_lossy(&output.stdout).trim()).ok()
+}
 
 impl Post {
-    pub fn extract_metadata(contents: String) -> Result<(Metadata, String), Box<dyn Error>> {
-        if !contents.starts_with("---\n"){
-            return Err("valid metadata delimiter")?
+    /// Split `contents` into front matter and markdown, also returns the line the markdown starts on.
+    /// Line endings are normalized to `\n`. `path` is used to infer metadata of posts without
+    /// front matter and in errors.
+    pub fn extract_metadata(contents: &str, path: &Path, config: &SiteConfig) -> Result<(Metadata, String, usize)> {
+        let contents = &front_matter::normalize(contents);
+        let error = |position: Option<Position>, message: String| Error::source(path, contents, position, message);
+        let start = Some(Position { line: 1, column: 1 });
+
+        let front_matter = front_matter::split(contents).map_err(|(position, message)| error(Some(position), message))?;
+        let (mut metadata, body, content_line) = match &front_matter {
+            Some(front_matter) => {
+                let parse_error = |(position, message): (Option<Position>, String)| error(position.or(start), message);
+                let mut metadata: Metadata = front_matter.parse().map_err(parse_error)?;
+                metadata.extra = front_matter.extra(&metadata).map_err(parse_error)?;
+                (metadata, front_matter.body, front_matter.body_line)
+            }
+            None => infer_metadata(contents, path).ok_or_else(|| {
+                let message = "no front matter, a post without --- or +++ front matter needs a `# Title` \
+                    heading and a file name starting with its date, e.g. 2024-01-31-my-post.md";
+                error(start, message.to_string())
+            })?,
+        };
+        // for values that are valid yaml or toml but not valid for the blog
+        let field_position = |key: &str| {
+            front_matter
+                .as_ref()
+                .and_then(|f| f.key_line(key))
+                .map(|line| Position { line, column: 1 })
+        };
+        metadata.slug = metadata.slug.trim().to_string();
+        if metadata.slug.is_empty() {
+            metadata.slug = slugify(&metadata.title);
+        }
+        if let Err(message) = check_slug(&metadata.slug) {
+            return Err(error(field_position("slug"), message));
+        }
+        for alias in &metadata.aliases {
+            let message = match alias.strip_prefix('/') {
+                None => format!("alias {alias:?} must start with /"),
+                Some("") => "alias \"/\" would replace the home page".to_string(),
+                Some(path) if !is_plain_path(path) => format!("alias {alias:?} can only contain plain folder names"),
+                Some(_) => continue,
+            };
+            return Err(error(field_position("aliases"), message));
+        }
+        for taxonomy in &config.taxonomies {
+            let terms = match metadata.extra.get(&taxonomy.key) {
+                None => continue,
+                Some(value) => terms(value).ok_or_else(|| {
+                    let message = format!("`{}` must be a term or a list of terms", taxonomy.key);
+                    error(field_position(&taxonomy.key), message)
+                })?,
+            };
+            metadata.taxonomies.insert(taxonomy.name.clone(), terms);
         }
-        
-        let slice = &contents[4..]; 
+        metadata.permalink = permalink(&config.section_of(path).permalink, &metadata);
+        metadata.url = format!("{}{}", config.url, metadata.permalink);
 
-        let metadata_end = slice.find("---\n").expect("valid metadata delimiter");
-        let metadata = &slice[..metadata_end];  
-        let contents = &slice[metadata_end+4..]; 
+        Ok((metadata, body.to_owned(), content_line))
+    }
+    pub fn from_string(contents: String, config: &SiteConfig) -> Result<Post>  {
+        Post::parse(Path::new(""), &contents, config)
+    }
+    /// Parse the contents of the post at `path`.
+    pub fn parse(path: &Path, contents: &str, config: &SiteConfig) -> Result<Post> {
+        let (metadata, contents, content_line) = Post::extract_metadata(contents, path, config)?; 
+        let summary = match &metadata.description {
+            Some(description) => description.trim().to_string(),
+            None => markdown::excerpt(&contents),
+        };
 
+        let section = config.section_of(path).name;
+        let bundle = is_bundle_index(config, path);
+        Ok(Post{metadata, contents, path: path.to_path_buf(), section, content_line, summary, word_count: 0, reading_time: 0, bundle})
+    }
+    /// Take the word count and reading time from the rendered markdown of the post.
+    pub fn set_word_count(&mut self, rendered: &Rendered) {
+        self.word_count = rendered.word_count;
+        self.reading_time = markdown::reading_time(rendered.word_count);
+    }
+    pub fn load(path: &Path, config: &SiteConfig) -> Result<Post> {
+        let contents = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+        let mut post = Post::parse(path, &contents, config)?;
+        if config.updated_from_git && post.metadata.updated.is_none() {
+            // commits on the publication day are part of writing the post
+            let date = post.metadata.date.date_naive();
+            post.metadata.updated = git_updated(path).filter(|updated| updated.date_naive() > date);
+        }
+        Ok(post)
+    }
+
+    /// Folder of a post written as a page bundle, `<folder>/index.md` along with its images and files.
+    pub fn bundle_dir(&self) -> Option<&Path> {
+        if self.bundle {
+            self.path.parent()
+        } else {
+            None
+        }
+    }
+
+    /// Url of the output folder of the post, `/posts/:slug.html` permalinks get `/posts/:slug/`.
+    pub fn folder_url(&self) -> String {
+        match self.metadata.permalink.strip_suffix(".html") {
+            Some(page) => format!("{page}/"),
+            None => self.metadata.permalink.clone(),
+        }
+    }
+
+    /// `updated`, or `date` if the post was never updated.
+    pub fn last_modified(&self) -> PostDate {
+        self.metadata.updated.unwrap_or(self.metadata.date)
+    }
+    pub fn render(&self, config: &SiteConfig) -> Result<Rendered> {
+        let folder_url = self.folder_url();
+        let location = Location {
+            posts_dir: Path::new(&config.posts_dir),
+            bundle: self.bundle_dir().map(|dir| (dir, folder_url.as_str())),
+        };
+        markdown::parse_markdown(&self.contents, location).map_err(|e| e.in_file(&self.path, &self.contents, self.content_line))
+    }
+}
 
-        let mut metadata: serde_yaml::Value = serde_yaml::from_str(metadata).unwrap();
-        let slug = slugify(metadata["title"].as_str().unwrap());
-        let url = format!("{}/posts/{}/", BLOG_URL, slug);
-        metadata["slug"] = slug.into();
-        metadata["url"] = url.into(); 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        let metadata: Metadata = serde_yaml::from_value(metadata)?;
-        Ok((metadata, contents.to_owned()))
+    #[test]
+    fn post_dates() {
+        let date = PostDate::parse("2023-01-31").unwrap();
+        assert!(!date.has_time);
+        assert_eq!(date.datetime.to_rfc3339(), "2023-01-31T00:00:00+00:00");
+        let date = PostDate::parse("2023-01-31T09:00:00+02:00").unwrap();
+        assert!(date.has_time);
+        assert_eq!(date.to_utc().to_rfc3339(), "2023-01-31T07:00:00+00:00");
+        assert_eq!(PostDate::parse("2023-01-31 09:00 +02:00").unwrap(), date);
+        assert!(PostDate::parse("2023-13-01").is_err());
     }
-    pub fn from_string(contents: String) -> Result<Post, Box<dyn Error>>  {
-        let (metadata, contents) = Post::extract_metadata(contents)?; 
 
-        Ok(Post{metadata, contents})
+    #[test]
+    fn toml_datetime_in_post() {
+        let contents = "+++\ntitle = \"A\"\ndate = 2023-01-31T09:00:00+02:00\ntags = []\n+++\ntext";
+        let post = Post::parse(Path::new("a.md"), contents, &SiteConfig::default()).unwrap();
+        assert_eq!(
+            post.metadata.date,
+            PostDate::parse("2023-01-31T09:00:00+02:00").unwrap()
+        );
+        assert_eq!(post.content_line, 6);
     }
-    pub fn render(&self) -> (String, bool, HashMap<String, f64>) {
-        markdown::parse_markdown(&self.contents)
+
+    #[test]
+    fn post_error_line() {
+        let contents = "\u{feff}---\r\ntitle: A\r\ndate: 2023-13-01\r\ntags: []\r\n---\r\n";
+        let error = Post::parse(Path::new("a.md"), contents, &SiteConfig::default()).unwrap_err();
+        match error {
+            crate::Error::Source { position, .. } => assert_eq!(position.map(|p| p.line), Some(3)),
+            error => panic!("unexpected error {error}"),
+        }
+    }
+
+    #[test]
+    fn inferred_metadata() {
+        let contents = "\n# My Post\n\nText\n";
+        let post = Post::parse(
+            Path::new("posts/2024-01-31-my-post.md"),
+            contents,
+            &SiteConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(post.metadata.title, "My Post");
+        assert_eq!(post.metadata.slug, "my-post");
+        assert_eq!(post.metadata.date, PostDate::parse("2024-01-31").unwrap());
+        assert_eq!(post.contents, "\nText\n");
+        assert_eq!(post.content_line, 3);
+    }
+
+    #[test]
+    fn inferred_metadata_needs_heading_and_date() {
+        let config = SiteConfig::default();
+        assert!(Post::parse(Path::new("2024-01-31-a.md"), "Text\n", &config).is_err());
+        assert!(Post::parse(Path::new("my-post.md"), "# Title\n", &config).is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn slug_from_file_name() {
+        let path = Path::new("posts/2024-01-31-My Post.md");
+        let post = Post::parse(path, "# Title\n", &SiteConfig::default()).unwrap();
+        assert_eq!(post.metadata.slug, "my-post");
+        assert_eq!(post.metadata.permalink, "/posts/my-post/");
+    }
+
+    #[test]
+    fn slugs_and_aliases_stay_in_their_folder() {
+        let config = SiteConfig::default();
+        let post = |front_matter: &str| {
+            let contents = format!("---\ntitle: A\ndate: 2024-01-31\ntags: []\n{front_matter}\n---\n");
+            Post::parse(Path::new("posts/a.md"), &contents, &config)
+        };
+        assert!(post("slug: b").is_ok());
+        assert!(post("slug: ..").is_err());
+        assert!(post("slug: a/b").is_err());
+        assert!(post("aliases: [/old/a/]").is_ok());
+        assert!(post("aliases: [/]").is_err());
+        assert!(post("aliases: [/../../escaped/]").is_err());
+        assert!(post("aliases: [/old/./../a/]").is_err());
+    }
+}
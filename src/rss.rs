@@ -1,35 +1,71 @@
 use rss::{ChannelBuilder, Item, Guid};
+use rss::extension::{Extension, ExtensionMap};
+use crate::authors::{self, Authors};
 use crate::post::Post;
-use chrono::{TimeZone, Datelike};
+use std::collections::BTreeMap;
 
-pub fn generate_rss(posts: &Vec<Post>, blog_name: &str, blog_url: &str) -> String {
+const ATOM_NAMESPACE: &str = "http://www.w3.org/2005/Atom";
+const DUBLIN_CORE_NAMESPACE: &str = "http://purl.org/dc/elements/1.1/";
+
+fn extension(name: &str, value: String) -> Extension {
+    let mut extension = Extension::default();
+    extension.set_name(name);
+    extension.set_value(value);
+    extension
+}
+
+pub fn generate_rss(posts: &[Post], authors: &Authors, blog_name: &str, blog_url: &str) -> String {
 
     let mut channel = ChannelBuilder::default()
     .title(blog_name.to_string())
     .link(blog_url.to_string())
     .description("blog".to_string())
+    .namespaces(BTreeMap::from([
+        ("atom".to_string(), ATOM_NAMESPACE.to_string()),
+        ("dc".to_string(), DUBLIN_CORE_NAMESPACE.to_string()),
+    ]))
     .build();
 
+    if let Some(last_modified) = posts.iter().map(Post::last_modified).max() {
+        channel.set_last_build_date(last_modified.datetime.to_rfc2822());
+    }
+
     let mut items: Vec<Item> = Vec::new();
 
     for post in posts {
         // rss feeds require rfc2822 format
-        let dt = post.metadata.date; 
-        let dt = chrono::Utc.with_ymd_and_hms(dt.year(), dt.month(), dt.day(), 0, 0, 0);
-        let dt = dt.unwrap();
+        let dt = post.metadata.date.datetime;
 
         let mut item = Item::default();
         item.set_title(post.metadata.title.clone());
         item.set_pub_date(dt.to_rfc2822()); 
         item.set_link(post.metadata.url.clone());
-        // unique id for each post across the site
+        if !post.summary.is_empty() {
+            item.set_description(post.summary.clone());
+        }
+        // unique id for each post across the site, the absolute permalink
         let mut guid = Guid::default();
-        guid.set_value(&post.metadata.slug);
+        guid.set_value(&post.metadata.url);
         item.set_guid(guid);
+        let mut extensions = ExtensionMap::new();
+        // rss has no update date, atom's is commonly understood by readers
+        if let Some(updated) = post.metadata.updated {
+            let updated = extension("atom:updated", updated.datetime.to_rfc3339());
+            extensions.insert("atom".to_string(), BTreeMap::from([("updated".to_string(), vec![updated])]));
+        }
+        // rss authors must be email addresses, names go in dc:creator
+        let post_authors = authors::post_authors(authors, post);
+        if let Some(email) = post_authors.iter().find_map(|a| a.email.as_ref().map(|e| format!("{} ({})", e, a.name))) {
+            item.set_author(email);
+        }
+        if !post_authors.is_empty() {
+            let creators = post_authors.iter().map(|a| extension("dc:creator", a.name.clone())).collect();
+            extensions.insert("dc".to_string(), BTreeMap::from([("creator".to_string(), creators)]));
+        }
+        item.set_extensions(extensions);
         items.push(item); 
     }
     channel.set_items(items); 
 
-    let xml = channel.to_string();
-    xml
-}
\ No newline at end of file
+    channel.to_string()
+}
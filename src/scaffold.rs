@@ -0,0 +1,87 @@
+use crate::build;
+use crate::config::SiteConfig;
+use crate::error::{Error, Result};
+use chrono::NaiveDate;
+use serde::Serialize;
+use slug::slugify;
+use crate::post::BUNDLE_INDEX;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Front matter written for new posts, `Post::extract_metadata` fills in the rest.
+#[derive(Debug, Serialize)]
+struct FrontMatter<'a> {
+    title: &'a str,
+    date: NaiveDate,
+    tags: Vec<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    draft: bool,
+}
+
+/// A newly created post file.
+pub struct NewPost {
+    pub path: PathBuf,
+    /// Existing posts with the same slug, they would end up at the same url
+    pub collisions: Vec<PathBuf>,
+}
+
+/// Existing posts whose slug is `slug`. Posts that fail to load are skipped, `check` reports those.
+fn find_slug_collisions(config: &SiteConfig, section: &str, slug: &str) -> Result<Vec<PathBuf>> {
+    Ok(build::find_posts(config)?
+        .into_iter()
+        .filter(|path| {
+            build::load_post(config, path)
+                .map(|post| post.section == section && post.metadata.slug == slug)
+                .unwrap_or(false)
+        })
+        .collect())
+}
+
+/// Create `{dir}/{slug}.md`, or `{dir}/{slug}/index.md` for a page bundle, with front matter for
+/// `title`. `dir` is the folder of `section`, the posts folder if none is given. Never overwrites an existing file.
+pub fn new_post(
+    config: &SiteConfig,
+    title: &str,
+    tags: &[String],
+    draft: bool,
+    bundle: bool,
+    section: Option<&str>,
+) -> Result<NewPost> {
+    let slug = slugify(title);
+    if slug.is_empty() {
+        return Err(format!("Title {title:?} does not produce a valid slug").into());
+    }
+    let section = match section {
+        Some(name) => config.section(name).ok_or_else(|| format!("There is no section {name:?} in the config"))?,
+        None => config.main_section(),
+    };
+    let path = if bundle {
+        let dir = Path::new(&section.dir).join(&slug);
+        fs::create_dir_all(&dir).map_err(|e| Error::io(&dir, e))?;
+        dir.join(BUNDLE_INDEX)
+    } else {
+        let dir = Path::new(&section.dir);
+        fs::create_dir_all(dir).map_err(|e| Error::io(dir, e))?;
+        dir.join(format!("{slug}.md"))
+    };
+    let collisions = find_slug_collisions(config, &section.name, &slug)?;
+
+    let front_matter = FrontMatter {
+        title,
+        date: chrono::Local::now().date_naive(),
+        tags: tags.iter().map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect(),
+        draft,
+    };
+    let contents = format!("---\n{}---\n\n", serde_yaml::to_string(&front_matter).map_err(|e| e.to_string())?);
+
+    let mut file = match OpenOptions::new().write(true).create_new(true).open(&path) {
+        Ok(f) => f,
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+            return Err(format!("{} already exists", path.display()).into())
+        }
+        Err(err) => return Err(Error::io(&path, err)),
+    };
+    file.write_all(contents.as_bytes()).map_err(|e| Error::io(&path, e))?;
+    Ok(NewPost { path, collisions })
+}
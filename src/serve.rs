@@ -0,0 +1,266 @@
+use crate::config::SiteConfig;
+use crate::site::Site;
+use crate::watch::SiteWatcher;
+use crate::error::Result;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Build folder and cache manifest of `serve`, kept apart from the production build
+/// since it includes drafts and future posts and links to localhost.
+pub const SERVE_DIR: &str = ".serve";
+pub const SERVE_CACHE_FILE: &str = ".serve-cache.yml";
+
+const RELOAD_PATH: &str = "/__livereload";
+const RELOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Injected into html responses, never written to the build folder.
+/// Long polls the server and reloads the page once the build version changes.
+const RELOAD_SCRIPT: &str = r#"<script>
+(function () {
+    var version = null;
+    function poll() {
+        fetch("/__livereload" + (version === null ? "" : "?v=" + version), { cache: "no-store" })
+            .then(function (r) { return r.text(); })
+            .then(function (v) {
+                if (version !== null && v !== version) { location.reload(); return; }
+                version = v;
+                poll();
+            })
+            .catch(function () { setTimeout(poll, 1000); });
+    }
+    poll();
+})();
+</script>"#;
+
+/// Build version, bumped after every successful rebuild.
+#[derive(Default)]
+struct Reload {
+    version: Mutex<u64>,
+    changed: Condvar,
+}
+
+impl Reload {
+    fn bump(&self) {
+        *self.version.lock().expect("reload lock") += 1;
+        self.changed.notify_all();
+    }
+
+    /// Wait until the version differs from `seen`, or the timeout passes.
+    fn wait(&self, seen: Option<u64>) -> u64 {
+        let version = self.version.lock().expect("reload lock");
+        match seen {
+            Some(seen) => {
+                let (version, _) = self
+                    .changed
+                    .wait_timeout_while(version, RELOAD_TIMEOUT, |v| *v == seen)
+                    .expect("reload lock");
+                *version
+            }
+            None => *version,
+        }
+    }
+}
+
+enum Resolved {
+    File(PathBuf),
+    Redirect(String),
+    NotFound,
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()).unwrap_or_default() {
+        "html" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" => "text/javascript; charset=utf-8",
+        "xml" => "application/xml; charset=utf-8",
+        "txt" => "text/plain; charset=utf-8",
+        "json" => "application/json",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "ico" => "image/x-icon",
+        "woff2" => "font/woff2",
+        "woff" => "font/woff",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok());
+        match (bytes[i], hex.and_then(|h| u8::from_str_radix(h, 16).ok())) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Map a url path to a file in the build folder, `/posts/slug/` resolves to `posts/slug/index.html`.
+fn resolve(root: &Path, url_path: &str) -> Resolved {
+    let decoded = percent_decode(url_path);
+    let relative = Path::new(decoded.trim_start_matches('/'));
+    if relative.components().any(|c| !matches!(c, Component::Normal(_))) {
+        return Resolved::NotFound;
+    }
+    let path = root.join(relative);
+
+    if path.is_dir() {
+        if !url_path.ends_with('/') {
+            return Resolved::Redirect(format!("{url_path}/"));
+        }
+        let index = path.join("index.html");
+        if index.is_file() {
+            return Resolved::File(index);
+        }
+    } else if path.is_file() {
+        return Resolved::File(path);
+    }
+    Resolved::NotFound
+}
+
+fn inject_reload_script(html: &[u8]) -> Vec<u8> {
+    let html = String::from_utf8_lossy(html);
+    let html = match html.rfind("</body>") {
+        Some(i) => format!("{}{}{}", &html[..i], RELOAD_SCRIPT, &html[i..]),
+        None => format!("{}{}", html, RELOAD_SCRIPT),
+    };
+    html.into_bytes()
+}
+
+fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+    head_only: bool,
+) -> io::Result<()> {
+    let mut response = format!(
+        "HTTP/1.1 {status}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n",
+        body.len()
+    );
+    for (name, value) in headers {
+        response.push_str(&format!("{name}: {value}\r\n"));
+    }
+    response.push_str("\r\n");
+    stream.write_all(response.as_bytes())?;
+    if !head_only {
+        stream.write_all(body)?;
+    }
+    stream.flush()
+}
+
+fn serve_file(stream: &mut TcpStream, status: &str, path: &Path, head_only: bool) -> io::Result<()> {
+    let mut body = fs::read(path)?;
+    let content_type = content_type(path);
+    if content_type.starts_with("text/html") {
+        body = inject_reload_script(&body);
+    }
+    respond(stream, status, &[("Content-Type", content_type)], &body, head_only)
+}
+
+fn handle(mut stream: TcpStream, root: &Path, reload: &Reload) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // headers are not needed, read them so the client does not see a reset connection
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let target = parts.next().unwrap_or("/");
+    let head_only = method == "HEAD";
+    if method != "GET" && !head_only {
+        return respond(&mut stream, "405 Method Not Allowed", &[], b"", false);
+    }
+    let (url_path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    if url_path == RELOAD_PATH {
+        let seen = query
+            .split('&')
+            .find_map(|p| p.strip_prefix("v="))
+            .and_then(|v| v.parse().ok());
+        let version = reload.wait(seen).to_string();
+        let headers = [("Content-Type", "text/plain; charset=utf-8")];
+        return respond(&mut stream, "200 OK", &headers, version.as_bytes(), head_only);
+    }
+
+    match resolve(root, url_path) {
+        Resolved::File(path) => serve_file(&mut stream, "200 OK", &path, head_only),
+        Resolved::Redirect(location) => {
+            respond(&mut stream, "301 Moved Permanently", &[("Location", &location)], b"", head_only)
+        }
+        Resolved::NotFound => {
+            let not_found = root.join("404.html");
+            if not_found.is_file() {
+                serve_file(&mut stream, "404 Not Found", &not_found, head_only)
+            } else {
+                let headers = [("Content-Type", "text/plain; charset=utf-8")];
+                respond(&mut stream, "404 Not Found", &headers, b"Not found", head_only)
+            }
+        }
+    }
+}
+
+/// Config of the local dev server: drafts and future posts included, urls on localhost and
+/// written to `SERVE_DIR` instead of the build folder.
+pub fn serve_config(mut config: SiteConfig, port: u16) -> SiteConfig {
+    config.url = format!("http://127.0.0.1:{port}");
+    config.drafts = true;
+    config.future = true;
+    config.build_dir = SERVE_DIR.to_string();
+    config.cache_file = SERVE_CACHE_FILE.to_string();
+    config
+}
+
+/// Build the site, serve the build folder on `port` and rebuild whenever the sources change.
+pub fn serve(site: Site, port: u16) -> Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).map_err(|e| format!("Could not bind port {port}: {e}"))?;
+    let root = PathBuf::from(&site.config.build_dir);
+    let mut watcher = SiteWatcher::new(site);
+    log::info!("Serving {} at http://127.0.0.1:{}/", root.display(), port);
+
+    let reload = Arc::new(Reload::default());
+    {
+        let reload = Arc::clone(&reload);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let reload = Arc::clone(&reload);
+                let root = root.clone();
+                thread::spawn(move || {
+                    if let Err(e) = handle(stream, &root, &reload) {
+                        log::warn!("Request failed: {}", e);
+                    }
+                });
+            }
+        });
+    }
+
+    loop {
+        if watcher.wait_and_rebuild() {
+            reload.bump();
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use crate::authors::{self, Authors};
+use crate::build;
+use crate::config::SiteConfig;
+use crate::html;
+use crate::page::StaticPage;
+use crate::post::Post;
+use crate::error::{Error, Result};
+use std::io;
+use std::path::Path;
+use tera::Tera;
+
+/// A blog: its config, templates, authors, posts and standalone pages.
+///
+/// ```no_run
+/// let mut site = blog::Site::load(std::path::Path::new("blog.toml"))?;
+/// site.build()?;
+/// # Ok::<(), blog::Error>(())
+/// ```
+pub struct Site {
+    pub config: SiteConfig,
+    templates: Tera,
+    authors: Authors,
+    posts: Vec<Post>,
+    pages: Vec<StaticPage>,
+    /// Rebuilt on every change by `build --watch` and `serve`
+    watched: bool,
+}
+
+impl Site {
+    /// Site for `config` with its templates and authors loaded, posts and pages are loaded with
+    /// `load_posts` and `load_pages`.
+    pub fn new(config: SiteConfig) -> Result<Site> {
+        let templates = html::load_templates(&config.templates_dir)?;
+        let authors = authors::load(&config)?;
+        Ok(Site { config, templates, authors, posts: Vec::new(), pages: Vec::new(), watched: false })
+    }
+
+    /// Read the config file, a missing file gives the default config.
+    pub fn load(config_file: &Path) -> Result<Site> {
+        Site::new(SiteConfig::load(config_file)?)
+    }
+
+    /// Whether the site is rebuilt on every change, posts are then saved while they are written.
+    pub fn is_watched(&self) -> bool {
+        self.watched
+    }
+
+    pub fn set_watched(&mut self, watched: bool) {
+        self.watched = watched;
+    }
+
+    pub fn templates(&self) -> &Tera {
+        &self.templates
+    }
+
+    /// Parse the templates again, on error the previous templates are kept.
+    pub fn reload_templates(&mut self) -> Result<()> {
+        self.templates = html::load_templates(&self.config.templates_dir)?;
+        Ok(())
+    }
+
+    pub fn authors(&self) -> &Authors {
+        &self.authors
+    }
+
+    /// Read the authors file again, on error the previous authors are kept.
+    pub fn reload_authors(&mut self) -> Result<()> {
+        self.authors = authors::load(&self.config)?;
+        Ok(())
+    }
+
+    pub fn posts(&self) -> &[Post] {
+        &self.posts
+    }
+
+    pub fn set_posts(&mut self, posts: Vec<Post>) {
+        self.posts = posts;
+    }
+
+    /// Read every post in the posts folder. When some posts are invalid the markdown of the
+    /// others is rendered too, so a single run reports every invalid post.
+    pub fn load_posts(&mut self) -> Result<&[Post]> {
+        let (posts, mut errors) = build::load_posts_with_errors(&self.config)?;
+        if let Err(e) = authors::check(&self.config, &self.authors, &posts) {
+            errors.push(e);
+        }
+        if !errors.is_empty() {
+            errors.extend(build::render_errors(&self.config, &posts));
+            Error::collect(errors)?;
+        }
+        self.posts = posts;
+        Ok(&self.posts)
+    }
+
+    pub fn pages(&self) -> &[StaticPage] {
+        &self.pages
+    }
+
+    /// Read every page in the pages folder, after `load_posts` as pages cannot use the urls of posts.
+    pub fn load_pages(&mut self) -> Result<&[StaticPage]> {
+        self.pages = build::load_pages(&self.config, &self.posts)?;
+        Ok(&self.pages)
+    }
+
+    /// Load the posts and render every page without writing anything, returns the number of pages.
+    pub fn check(&mut self) -> Result<usize> {
+        self.load_posts()?;
+        self.load_pages()?;
+        build::check_site(self)
+    }
+
+    /// Load the posts and write the site to the build folder.
+    pub fn build(&mut self) -> Result<()> {
+        build::build_site(self)?;
+        Ok(())
+    }
+
+    /// Delete the build folder and the build cache, returns false if there was nothing to delete.
+    pub fn clean(&self) -> io::Result<bool> {
+        build::clean(&self.config)
+    }
+}
@@ -0,0 +1,33 @@
+use crate::page::StaticPage;
+use crate::post::Post;
+
+/// Sitemap with the home page, every post and every standalone page, `lastmod` is the last update of a post.
+pub fn generate_sitemap(posts: &[Post], pages: &[StaticPage], blog_url: &str) -> String {
+    let mut sitemap = String::from(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    sitemap.push_str("\n<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+
+    let home_modified = posts.iter().map(Post::last_modified).max();
+    push_url(&mut sitemap, &format!("{blog_url}/"), home_modified.map(|d| d.datetime.to_rfc3339()));
+    for post in posts {
+        push_url(&mut sitemap, &post.metadata.url, Some(post.last_modified().datetime.to_rfc3339()));
+    }
+    for page in pages {
+        push_url(&mut sitemap, &format!("{blog_url}{}", page.url()), None);
+    }
+
+    sitemap.push_str("</urlset>\n");
+    sitemap
+}
+
+fn push_url(sitemap: &mut String, url: &str, lastmod: Option<String>) {
+    sitemap.push_str("  <url>\n");
+    sitemap.push_str(&format!("    <loc>{}</loc>\n", escape(url)));
+    if let Some(lastmod) = lastmod {
+        sitemap.push_str(&format!("    <lastmod>{lastmod}</lastmod>\n"));
+    }
+    sitemap.push_str("  </url>\n");
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&apos;")
+}
@@ -0,0 +1,350 @@
+use crate::authors;
+use crate::build;
+use crate::cache::BuildCache;
+use crate::config::SiteConfig;
+use crate::deps::{DependencyGraph, Target};
+use crate::site::Site;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use crate::error::{Error, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(300);
+
+/// Modification times of every file under the watched paths.
+pub type Snapshot = HashMap<PathBuf, SystemTime>;
+
+fn visit(path: &Path, snapshot: &mut Snapshot) {
+    let Ok(meta) = fs::metadata(path) else {
+        return;
+    };
+    if meta.is_dir() {
+        let Ok(entries) = fs::read_dir(path) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            visit(&entry.path(), snapshot);
+        }
+    } else if let Ok(modified) = meta.modified() {
+        snapshot.insert(path.to_path_buf(), modified);
+    }
+}
+
+pub fn snapshot(paths: &[PathBuf]) -> Snapshot {
+    let mut snapshot = HashMap::new();
+    for path in paths {
+        visit(path, &mut snapshot);
+    }
+    snapshot
+}
+
+/// Paths that were added, removed or modified between two snapshots.
+pub fn changes(old: &Snapshot, new: &Snapshot) -> Vec<PathBuf> {
+    let mut changed: Vec<PathBuf> = new
+        .iter()
+        .filter(|(path, modified)| old.get(*path) != Some(modified))
+        .map(|(path, _)| path.clone())
+        .chain(old.keys().filter(|path| !new.contains_key(*path)).cloned())
+        .collect();
+    changed.sort();
+    changed
+}
+
+/// Poll the watched paths, blocks until something changes and returns the changed files.
+/// Editors often write files in several steps so changes are collected until things settle.
+pub fn wait_for_changes(paths: &[PathBuf], last: &mut Snapshot) -> Vec<PathBuf> {
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        let mut current = snapshot(paths);
+        if changes(last, &current).is_empty() {
+            continue;
+        }
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            let next = snapshot(paths);
+            if changes(&current, &next).is_empty() {
+                break;
+            }
+            current = next;
+        }
+        let changed = changes(last, &current);
+        *last = current;
+        if !changed.is_empty() {
+            return changed;
+        }
+    }
+}
+
+/// Sources a build depends on.
+pub fn watched_paths(config: &SiteConfig) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = [
+        &config.posts_dir,
+        &config.images_dir,
+        &config.pages_dir,
+        &config.templates_dir,
+        &config.assets_dir,
+        &config.authors_file,
+    ]
+    .iter()
+    .map(PathBuf::from)
+    .collect();
+    paths.extend(config.sections.iter().map(|section| PathBuf::from(&section.dir)));
+    paths
+}
+
+/// Rebuilds the site when its sources change, only redoing the targets affected by the change.
+pub struct SiteWatcher {
+    site: Site,
+    watched: Vec<PathBuf>,
+    last: Snapshot,
+    /// None until a full build succeeded
+    graph: Option<DependencyGraph>,
+}
+
+impl SiteWatcher {
+    /// Do a full build and start watching. A failed build is reported and retried on the next change.
+    pub fn new(mut site: Site) -> SiteWatcher {
+        site.set_watched(true);
+        let watched = watched_paths(&site.config);
+        let last = snapshot(&watched);
+        let mut watcher = SiteWatcher { site, watched, last, graph: None };
+        if let Err(e) = watcher.full_build() {
+            log::error!("Build failed: {}", e);
+        }
+        watcher
+    }
+
+    pub fn site(&self) -> &Site {
+        &self.site
+    }
+
+    fn full_build(&mut self) -> Result<()> {
+        self.site.reload_templates()?;
+        let rendered = build::build_site(&mut self.site)?;
+        let posts = self.site.posts().to_vec();
+        let pages = self.site.pages().to_vec();
+        self.graph = Some(DependencyGraph::new(&self.site.config, posts, pages, rendered));
+        Ok(())
+    }
+
+    /// Block until sources change and rebuild, returns whether the rebuild succeeded.
+    pub fn wait_and_rebuild(&mut self) -> bool {
+        let changed = wait_for_changes(&self.watched, &mut self.last);
+        let names: Vec<String> = changed.iter().map(|p| p.display().to_string()).collect();
+        log::info!("Changed: {}", names.join(", "));
+
+        let time = Instant::now();
+        let result = match self.graph.take() {
+            Some(mut graph) => rebuild_changed(&mut self.site, &mut graph, &changed).map(|()| graph),
+            None => self.full_build().map(|()| self.graph.take().expect("graph after build")),
+        };
+        match result {
+            Ok(graph) => {
+                self.site.set_posts(graph.posts());
+                self.graph = Some(graph);
+                log::info!("Rebuilt in: {:.2?}", time.elapsed());
+                true
+            }
+            Err(e) => {
+                log::error!("Build failed: {}", e);
+                false
+            }
+        }
+    }
+}
+
+/// Load a changed post into the graph, or drop it if it was removed or is no longer published.
+fn reload_post(site: &Site, cache: &BuildCache, graph: &mut DependencyGraph, path: &Path) -> Result<()> {
+    let config = &site.config;
+    let post = if path.exists() { Some(build::load_post(config, path)?) } else { None };
+    let post = post.filter(|p| build::is_published(config, p));
+    if let Some(post) = &post {
+        authors::check(config, site.authors(), std::slice::from_ref(post))?;
+        build::check_urls(post, graph.posts().iter().filter(|p| p.path != path))?;
+        for page in site.pages() {
+            build::check_page_url(config, page, std::slice::from_ref(post))?;
+        }
+    }
+
+    let old_files = graph.bundle_files(path).to_vec();
+    if let Some(old) = graph.remove_post(path) {
+        cache.forget(&build::url_path(&old.metadata.permalink));
+        for alias in &old.metadata.aliases {
+            cache.forget(&build::url_path(alias));
+        }
+        for file in &old_files {
+            if let Some(dest) = build::bundle_file_dest(&old, file) {
+                cache.forget(&dest);
+            }
+        }
+    }
+    if let Some(post) = post {
+        graph.insert_post(post);
+    }
+    Ok(())
+}
+
+/// Load the pages again and bring the graph in line, returns whether the menu changed.
+/// Pages are few and small so all of them are reloaded, which also catches url clashes.
+fn reload_pages(site: &mut Site, cache: &BuildCache, graph: &mut DependencyGraph) -> Result<bool> {
+    let old_menu = build::menu(site.pages());
+    let old_pages = site.pages().to_vec();
+    site.load_pages()?;
+    for old in old_pages {
+        graph.remove_page(&old.path);
+        if !site.pages().iter().any(|p| p.url() == old.url()) {
+            cache.forget(&build::url_path(&old.url()));
+        }
+    }
+    for page in site.pages() {
+        graph.insert_page(page.clone());
+    }
+    Ok(build::menu(site.pages()) != old_menu)
+}
+
+/// Rebuild the targets affected by the changed files.
+/// Changed posts are loaded before anything is rendered so pages see the new list of posts.
+pub fn rebuild_changed(
+    site: &mut Site,
+    graph: &mut DependencyGraph,
+    changed: &[PathBuf],
+) -> Result<()> {
+    if changed.iter().any(|p| p.starts_with(&site.config.templates_dir)) {
+        site.reload_templates()?;
+    }
+    if changed.iter().any(|p| p == Path::new(&site.config.authors_file)) {
+        site.reload_authors()?;
+    }
+    let cache = BuildCache::resume(&site.config);
+    let mut targets = BTreeSet::new();
+    for path in changed {
+        targets.extend(graph.affected(&site.config, site.templates(), path));
+    }
+    if changed.iter().any(|p| build::is_page_path(&site.config, p)) && reload_pages(site, &cache, graph)? {
+        // every page shows the menu
+        targets.extend(graph.pages(&site.config));
+    }
+    let site = &*site;
+    let config = &site.config;
+
+    // load the changed posts, the pages they appear on in their new version are only known then
+    let changed_posts: Vec<PathBuf> = changed.iter().filter(|p| targets.contains(&Target::Post(p.to_path_buf()))).cloned().collect();
+    for path in &changed_posts {
+        reload_post(site, &cache, graph, path)?;
+    }
+    for path in &changed_posts {
+        targets.extend(graph.related(path));
+    }
+
+    // render posts and pages before the rest, the images they embed are only known once they are rendered
+    let posts = graph.posts();
+    let all_series = build::series(&posts);
+    let mut pages = Vec::new();
+    let mut images = Vec::new();
+    for target in &targets {
+        if let Target::Page(path) = target {
+            let Some(page) = graph.page(path).cloned() else {
+                continue;
+            };
+            let (page, rendered) = build::render_static_page(site, &page)?;
+            pages.push(page);
+            graph.set_rendered(config, path, &rendered);
+            images.extend(graph.post_images(path).iter().cloned().map(Target::Image));
+            continue;
+        }
+        let Target::Post(path) = target else {
+            continue;
+        };
+        let Some(post) = graph.post(path).cloned() else {
+            continue;
+        };
+        let (page, rendered) = build::render_post(site, &cache, &post, &posts, &all_series)?;
+        pages.push(page);
+        pages.extend(build::render_aliases(&post));
+        graph.set_rendered(config, path, &rendered);
+        images.extend(graph.post_images(path).iter().cloned().map(Target::Image));
+        images.extend(graph.bundle_files(path).iter().cloned().map(Target::Image));
+    }
+    targets.extend(images);
+
+    // listings show the word counts of the posts just rendered
+    let posts = graph.posts();
+    let tags = build::tags(&posts);
+    let series = build::series(&posts);
+    let sections = build::sections(&posts);
+    let taxonomy_terms: BTreeMap<&str, _> =
+        config.taxonomies.iter().map(|t| (t.name.as_str(), build::taxonomy_terms(&posts, &t.name))).collect();
+    let term_posts = |name: &str, term: &str| taxonomy_terms.get(name).and_then(|terms| terms.get(term));
+    let image_scales = graph.image_scales();
+    for target in &targets {
+        match target {
+            Target::Post(_) | Target::Page(_) => (),
+            Target::Index => pages.push(build::render_index(site, &posts)?),
+            Target::About if build::about_overridden(site) => (),
+            Target::About => pages.push(build::render_about(site)?),
+            Target::NotFound => pages.push(build::render_not_found(site)?),
+            Target::AllTags => pages.push(build::render_all_tags(site, &posts)?),
+            Target::Author(id) => match (site.authors().get(id), build::author_posts(&posts).get(id.as_str())) {
+                (Some(author), Some(author_posts)) => pages.push(build::render_author(site, author, author_posts)?),
+                _ => cache.forget(&build::url_path(&authors::author_permalink(&slug::slugify(id)))),
+            },
+            Target::Series(name) => match series.get(name.as_str()) {
+                Some(parts) => pages.push(build::render_series(site, name, parts)?),
+                None => cache.forget(&build::url_path(&build::series_permalink(name))),
+            },
+            Target::Tag(tag) => match tags.get(tag.as_str()) {
+                Some(tag_posts) => pages.push(build::render_tag(site, tag, tag_posts)?),
+                None => cache.forget(&build::tag_page_path(tag)),
+            },
+            Target::Section(name) => match config.sections.iter().find(|s| s.name == *name) {
+                Some(section) => {
+                    let section_posts = sections.get(name.as_str()).map(Vec::as_slice).unwrap_or_default();
+                    pages.push(build::render_section(site, section, section_posts)?)
+                }
+                None => cache.forget(&build::url_path(&build::section_permalink(name))),
+            },
+            Target::Term(name, term) => {
+                let taxonomy = config.taxonomies.iter().find(|t| t.name == *name);
+                match (taxonomy, term_posts(name, term)) {
+                    (Some(taxonomy), Some(term_posts)) => pages.push(build::render_term(site, taxonomy, term, term_posts)?),
+                    _ => cache.forget(&build::url_path(&build::term_permalink(name, term))),
+                }
+            }
+            Target::AllTerms(name) => match config.taxonomies.iter().find(|t| t.name == *name) {
+                Some(taxonomy) => pages.push(build::render_all_terms(site, taxonomy, &posts)?),
+                None => cache.forget(&build::url_path(&build::taxonomy_permalink(name))),
+            },
+            Target::TermFeed(name, term) => {
+                let taxonomy = config.taxonomies.iter().find(|t| t.name == *name && t.feeds);
+                match (taxonomy, term_posts(name, term)) {
+                    (Some(_), Some(term_posts)) => build::write_term_feed(site, &cache, name, term, term_posts)?,
+                    _ => cache.forget(&build::term_feed_path(name, term)),
+                }
+            }
+            Target::SectionFeed(name) => match config.sections.iter().find(|s| s.name == *name) {
+                Some(section) => build::write_section_feed(site, &cache, section, &posts)?,
+                None => cache.forget(&build::section_feed_path(name)),
+            },
+            Target::Feed => build::write_feed(site, &cache, &posts).map_err(|e| Error::io(Path::new(build::FEED_PATH), e))?,
+            Target::Sitemap => build::write_sitemap(config, &cache, &posts, site.pages())?,
+            Target::Redirects => build::write_redirects(config, &cache, &posts)?,
+            Target::Image(path) if path.exists() => {
+                let dest = graph.image_dest(config, path);
+                build::process_image(config, &cache, path, &dest, build::image_scale(path, &image_scales))?
+            }
+            Target::Image(path) => cache.forget(&graph.image_dest(config, path)),
+            Target::Asset(path) => {
+                let dest = path.strip_prefix(&config.assets_dir).map_err(|e| e.to_string())?;
+                if path.exists() {
+                    build::copy_asset(config, &cache, path, dest)?;
+                } else {
+                    cache.forget(dest);
+                }
+            }
+        }
+    }
+    build::write_pages(config, &cache, &pages)?;
+    build::finish(config, cache)
+}